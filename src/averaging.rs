@@ -0,0 +1,99 @@
+use std::f64::consts::PI;
+
+use crate::complex::Complex;
+use crate::fractal::Escape;
+use crate::trap::OrbitVisitor;
+
+/// The averaging colorings: a per-iteration term of the orbit, averaged over
+/// every step up to the escape.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Average {
+    /// `(1 + sin(density * arg z)) / 2`, which draws `density` stripes
+    /// around the set.
+    Stripe { density: f64 },
+    /// Where `|z|` falls between the bounds the triangle inequality puts on
+    /// `|z^d + c|`.
+    TriangleInequality,
+    /// The turn the orbit takes at each point, from 0 for straight on to 1
+    /// for straight back.
+    Curvature,
+}
+
+/// Collects an orbit's average for `Average`. Starts from the orbit's
+/// first point `z0` and parameter `c`.
+pub struct AverageVisitor {
+    average: Average,
+    c: Complex,
+    degree: f64,
+    /// The two points before the next one, the latest first.
+    previous: [Option<Complex>; 2],
+    sum: f64,
+    count: u64,
+}
+
+impl AverageVisitor {
+    pub fn new(average: Average, c: Complex, z0: Complex, degree: f64) -> Self {
+        AverageVisitor {
+            average,
+            c,
+            degree,
+            previous: [Some(z0), None],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    /// The term for `z`, following the previous points, or `None` where it
+    /// is undefined.
+    fn term(&self, z: Complex) -> Option<f64> {
+        match self.average {
+            Average::Stripe { density } => Some(0.5 + 0.5 * (density * z.im.atan2(z.re)).sin()),
+            Average::TriangleInequality => {
+                let power = self.previous[0]?.norm().powf(self.degree);
+                let c = self.c.norm();
+                let low = (power - c).abs();
+                let high = power + c;
+                (high > low).then(|| (z.norm() - low) / (high - low))
+            }
+            Average::Curvature => {
+                let step = z - self.previous[0]?;
+                let before = self.previous[0]? - self.previous[1]?;
+                let turn = step * before.conj();
+                (turn != Complex::ZERO).then(|| turn.im.atan2(turn.re).abs() / PI)
+            }
+        }
+    }
+
+    /// The average at `escape`, blended between the averages with and
+    /// without the escaping point by the fractional part of the smooth
+    /// iteration count, so that it is continuous across iteration bands.
+    /// NaN for the interior, and 0 for orbits that escape before any term
+    /// is defined.
+    pub fn value(&self, escape: &Escape, bailout: f64) -> f64 {
+        if !escape.escaped() {
+            return f64::NAN;
+        }
+        let after = self
+            .term(escape.z)
+            .map(|last| (self.sum + last) / (self.count + 1) as f64);
+        let before = self.sum / self.count as f64;
+        let after = match (self.count, after) {
+            (0, None) => return 0.0,
+            (0, Some(after)) => return after,
+            (_, None) => return before,
+            (_, Some(after)) => after,
+        };
+        let fraction = 1.0 + (bailout.ln() / escape.z.norm().ln()).ln() / self.degree.ln();
+        before + fraction.clamp(0.0, 1.0) * (after - before)
+    }
+}
+
+impl OrbitVisitor for AverageVisitor {
+    fn visit(&mut self, _: u64, z: Complex) {
+        if let Some(term) = self.term(z) {
+            self.sum += term;
+            self.count += 1;
+        }
+        self.previous = [Some(z), self.previous[0]];
+    }
+}
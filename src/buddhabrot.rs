@@ -0,0 +1,382 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use image::{ImageBuffer, Rgb, RgbImage};
+use rayon::prelude::*;
+
+use crate::complex::Complex;
+use crate::formula::Formula;
+use crate::fractal::{Exit, RenderOptions, RenderStats};
+use crate::progress::Reporter;
+use crate::random::SplitMix64;
+use crate::viewport::Viewport;
+
+/// Orbits sampled per unit of work handed to a thread.
+const CHUNK: u64 = 4096;
+
+/// Half the side of the square of `c` values sampled, which holds the whole
+/// set for every built-in formula.
+const SAMPLED_RADIUS: f64 = 2.0;
+
+/// Cells per side of the grid the importance map is estimated on.
+const IMPORTANCE_CELLS: usize = 256;
+
+/// How many times more often cells on the boundary are sampled than others.
+const BOUNDARY_WEIGHT: u32 = 16;
+
+/// Which orbits are accumulated into the density buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variant {
+    /// Orbits that escape within `max_iterations`, in grayscale.
+    Buddhabrot,
+    /// Three Buddhabrots with the iteration limits of `DensityOptions::limits`
+    /// as the red, green and blue channels.
+    Nebulabrot,
+    /// Orbits that do not escape within `max_iterations`, in grayscale.
+    AntiBuddhabrot,
+}
+
+impl Variant {
+    pub const NAMES: &'static [&'static str] = &["buddhabrot", "nebulabrot", "anti-buddhabrot"];
+
+    pub fn from_name(name: &str) -> Option<Variant> {
+        match name {
+            "buddhabrot" => Some(Variant::Buddhabrot),
+            "nebulabrot" => Some(Variant::Nebulabrot),
+            "anti-buddhabrot" => Some(Variant::AntiBuddhabrot),
+            _ => None,
+        }
+    }
+}
+
+/// How densities are mapped to brightness before gamma is applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tone {
+    Linear,
+    /// `ln(1 + density)`, which brings out faint orbits.
+    Log,
+}
+
+impl Tone {
+    pub const NAMES: &'static [&'static str] = &["linear", "log"];
+
+    pub fn from_name(name: &str) -> Option<Tone> {
+        match name {
+            "log" => Some(Tone::Log),
+            "linear" => Some(Tone::Linear),
+            _ => None,
+        }
+    }
+}
+
+/// Settings for orbit density renders, beside the shared `RenderOptions`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DensityOptions {
+    pub variant: Variant,
+    /// Number of `c` values sampled.
+    pub samples: u64,
+    pub seed: u64,
+    /// Red, green and blue iteration limits for `Variant::Nebulabrot`.
+    pub limits: [u64; 3],
+    /// Sample cells near the boundary of the set more often, weighting their
+    /// orbits down to match, instead of sampling uniformly.
+    pub importance: bool,
+    pub tone: Tone,
+    pub gamma: f64,
+}
+
+/// Renders the density of the orbits of random parameters `c` of `formula`
+/// inside `viewport`. Samples are drawn from a fixed sequence for
+/// `density.seed`, so the image does not depend on the number of threads.
+pub fn render<F: Formula>(
+    formula: &F,
+    viewport: &Viewport,
+    options: &RenderOptions,
+    density: &DensityOptions,
+) -> (RgbImage, RenderStats) {
+    let (counts, stats) = accumulate(formula, viewport, options, density);
+    if stats.cancelled {
+        return (ImageBuffer::new(viewport.width, viewport.height), stats);
+    }
+
+    let channels = channels(density.variant);
+    let pixels = (viewport.width * viewport.height) as usize;
+    let brightness: Vec<Vec<u8>> = (0..channels)
+        .map(|channel| {
+            let values: Vec<u64> = (0..pixels)
+                .map(|pixel| counts[pixel * channels + channel])
+                .collect();
+            tone_map(&values, density.tone, density.gamma)
+        })
+        .collect();
+    let img = ImageBuffer::from_fn(viewport.width, viewport.height, |x, y| {
+        let pixel = (y * viewport.width + x) as usize;
+        let channel = |k: usize| brightness[k.min(channels - 1)][pixel];
+        Rgb([channel(0), channel(1), channel(2)])
+    });
+    (img, stats)
+}
+
+fn channels(variant: Variant) -> usize {
+    match variant {
+        Variant::Nebulabrot => 3,
+        Variant::Buddhabrot | Variant::AntiBuddhabrot => 1,
+    }
+}
+
+/// Weighted orbit point counts per pixel and channel, interleaved.
+fn accumulate<F: Formula>(
+    formula: &F,
+    viewport: &Viewport,
+    options: &RenderOptions,
+    density: &DensityOptions,
+) -> (Vec<u64>, RenderStats) {
+    let channels = channels(density.variant);
+    let limits: Vec<u64> = match density.variant {
+        Variant::Nebulabrot => density.limits.to_vec(),
+        Variant::Buddhabrot | Variant::AntiBuddhabrot => vec![options.max_iterations],
+    };
+    let longest = limits.iter().copied().max().unwrap_or(0);
+    let bailout = options.bailout.unwrap_or(formula.bailout());
+    let importance = Importance::new(formula, bailout, longest, density.importance);
+
+    let counts: Vec<AtomicU64> = (0..viewport.width as usize * viewport.height as usize * channels)
+        .map(|_| AtomicU64::new(0))
+        .collect();
+    let add = |points: &[Complex], channel: usize, units: u32| {
+        for &z in points {
+            let (x, y) = viewport.complex_to_pixel(z);
+            if x >= 0.0 && y >= 0.0 && x < viewport.width as f64 && y < viewport.height as f64 {
+                let pixel = y as usize * viewport.width as usize + x as usize;
+                counts[pixel * channels + channel].fetch_add(units as u64, Ordering::Relaxed);
+            }
+        }
+    };
+
+    let reporter = Reporter::new(options.progress.as_ref(), density.samples);
+    let chunks = density.samples.div_ceil(CHUNK) as usize;
+    let next = AtomicUsize::new(0);
+    let stats = (0..rayon::current_num_threads())
+        .into_par_iter()
+        .map(|_| {
+            let mut stats = RenderStats::default();
+            let mut points = Vec::new();
+            loop {
+                if options.cancel.is_cancelled() {
+                    stats.cancelled = true;
+                    break;
+                }
+                let chunk = next.fetch_add(1, Ordering::Relaxed);
+                if chunk >= chunks {
+                    break;
+                }
+                let first = chunk as u64 * CHUNK;
+                let count = CHUNK.min(density.samples - first);
+                let mut random = SplitMix64::for_index(density.seed, chunk as u64);
+                for _ in 0..count {
+                    let (c, units) = importance.sample(&mut random);
+                    if density.variant != Variant::AntiBuddhabrot
+                        && options.interior_checks
+                        && formula.known_interior(c)
+                    {
+                        stats.record(Exit::KnownInterior);
+                        continue;
+                    }
+                    let escaped = orbit(formula, c, longest, bailout, &mut points);
+                    stats.record(match escaped {
+                        Some(_) => Exit::Escaped,
+                        None => Exit::MaxIterations,
+                    });
+                    match (density.variant, escaped) {
+                        (Variant::AntiBuddhabrot, None) => add(&points, 0, units),
+                        (Variant::Buddhabrot | Variant::Nebulabrot, Some(iterations)) => {
+                            for (channel, &limit) in limits.iter().enumerate() {
+                                if iterations < limit {
+                                    add(&points, channel, units);
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                reporter.advance(count);
+            }
+            stats
+        })
+        .reduce(RenderStats::default, |a, b| a + b);
+
+    let counts = counts.into_iter().map(AtomicU64::into_inner).collect();
+    (counts, stats)
+}
+
+/// Iterates `formula` for parameter `c`, collecting every point of the orbit
+/// after the starting one into `points`, up to and including the first one
+/// outside the bailout. Returns the iteration at which the orbit escaped, or
+/// `None` if it did not within `limit` steps.
+fn orbit<F: Formula>(
+    formula: &F,
+    c: Complex,
+    limit: u64,
+    bailout: f64,
+    points: &mut Vec<Complex>,
+) -> Option<u64> {
+    points.clear();
+    let mut z = formula.initial_z(c);
+    for iterations in 0..limit {
+        if z.norm_sqr() > bailout * bailout {
+            return Some(iterations);
+        }
+        z = formula.step(z, c);
+        points.push(z);
+    }
+    None
+}
+
+/// A piecewise-constant distribution over a grid of cells covering the
+/// sampled square, with the weight that makes each cell's samples count as
+/// much as uniform sampling would.
+struct Importance {
+    /// Cumulative cell weights.
+    cumulative: Vec<u64>,
+    boundary: Vec<bool>,
+}
+
+impl Importance {
+    /// Marks cells where a 4 x 4 grid of test points contains both escaping
+    /// and non-escaping parameters as boundary cells, unless `enabled` is
+    /// false, which samples uniformly.
+    fn new<F: Formula>(formula: &F, bailout: f64, limit: u64, enabled: bool) -> Self {
+        let cells = IMPORTANCE_CELLS * IMPORTANCE_CELLS;
+        let boundary: Vec<bool> = (0..cells)
+            .into_par_iter()
+            .map(|cell| {
+                if !enabled {
+                    return false;
+                }
+                let mut points = Vec::new();
+                let escapes = (0..16).map(|k| {
+                    let offset = ((k % 4) as f64 + 0.5) / 4.0;
+                    let c = Self::position(cell, offset, ((k / 4) as f64 + 0.5) / 4.0);
+                    orbit(formula, c, limit.min(1000), bailout, &mut points).is_some()
+                });
+                let escaped = escapes.filter(|&escaped| escaped).count();
+                escaped > 0 && escaped < 16
+            })
+            .collect();
+
+        let mut total = 0;
+        let cumulative = boundary
+            .iter()
+            .map(|&boundary| {
+                total += if boundary { BOUNDARY_WEIGHT as u64 } else { 1 };
+                total
+            })
+            .collect();
+        Importance {
+            cumulative,
+            boundary,
+        }
+    }
+
+    /// The point at fractions `(u, v)` across `cell`.
+    fn position(cell: usize, u: f64, v: f64) -> Complex {
+        let size = 2.0 * SAMPLED_RADIUS / IMPORTANCE_CELLS as f64;
+        let (column, row) = (cell % IMPORTANCE_CELLS, cell / IMPORTANCE_CELLS);
+        Complex::new(
+            -SAMPLED_RADIUS + (column as f64 + u) * size,
+            -SAMPLED_RADIUS + (row as f64 + v) * size,
+        )
+    }
+
+    /// A random parameter and the integer weight of its orbit.
+    fn sample(&self, random: &mut SplitMix64) -> (Complex, u32) {
+        let total = *self.cumulative.last().unwrap();
+        let target = random.next_u64() % total;
+        let cell = self.cumulative.partition_point(|&sum| sum <= target);
+        let c = Self::position(cell, random.next_f64(), random.next_f64());
+        let units = match self.boundary[cell] {
+            true => 1,
+            false => BOUNDARY_WEIGHT,
+        };
+        (c, units)
+    }
+}
+
+/// Brightness of each count, relative to the largest.
+fn tone_map(counts: &[u64], tone: Tone, gamma: f64) -> Vec<u8> {
+    let curve = |count: u64| match tone {
+        Tone::Linear => count as f64,
+        Tone::Log => (count as f64).ln_1p(),
+    };
+    let top = curve(counts.iter().copied().max().unwrap_or(0)).max(f64::MIN_POSITIVE);
+    counts
+        .iter()
+        .map(|&count| ((curve(count) / top).powf(1.0 / gamma) * 255.0).round() as u8)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formula::Quadratic;
+
+    fn density(variant: Variant, importance: bool) -> DensityOptions {
+        DensityOptions {
+            variant,
+            samples: 100_000,
+            seed: 7,
+            limits: [200, 50, 20],
+            importance,
+            tone: Tone::Log,
+            gamma: 2.0,
+        }
+    }
+
+    fn view() -> Viewport {
+        Viewport::new(Complex::new(-0.5, 0.0), 3.0, 48, 48)
+    }
+
+    #[test]
+    fn density_renders_are_repeatable() {
+        let options = RenderOptions::new(200);
+        for variant in [
+            Variant::Buddhabrot,
+            Variant::Nebulabrot,
+            Variant::AntiBuddhabrot,
+        ] {
+            let first = render(&Quadratic, &view(), &options, &density(variant, true));
+            let again = rayon::ThreadPoolBuilder::new()
+                .num_threads(3)
+                .build()
+                .unwrap()
+                .install(|| render(&Quadratic, &view(), &options, &density(variant, true)));
+            assert_eq!(first.0, again.0, "{:?}", variant);
+            assert!(first.0.pixels().any(|pixel| pixel.0 != [0, 0, 0]));
+        }
+    }
+
+    #[test]
+    fn importance_sampling_keeps_density() {
+        let options = RenderOptions::new(200);
+        // Share of the total density in each 12 x 12 block of the view.
+        let shares = |importance: bool| {
+            let density = density(Variant::Buddhabrot, importance);
+            let (counts, _) = accumulate(&Quadratic, &view(), &options, &density);
+            let mut blocks = [0.0; 16];
+            for (pixel, &count) in counts.iter().enumerate() {
+                let (x, y) = (pixel % 48 / 12, pixel / 48 / 12);
+                blocks[y * 4 + x] += count as f64;
+            }
+            let total: f64 = blocks.iter().sum();
+            blocks.map(|block| block / total)
+        };
+        let (uniform, weighted) = (shares(false), shares(true));
+        for (a, b) in uniform.iter().zip(weighted) {
+            assert!(
+                (a - b).abs() < 0.005 + 0.1 * a,
+                "{:?} {:?}",
+                uniform,
+                weighted
+            );
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::str::FromStr;
+
+/// Options read with `Args::flag`. These only take the next token as their
+/// value when it is an on/off word, so a bare `--deep` or `--stream` never
+/// swallows the positional argument after it.
+const FLAGS: &[&str] = &[
+    "deep",
+    "stream",
+    "resume",
+    "progress",
+    "interior-checks",
+    "series",
+    "importance",
+];
+
+const FLAG_VALUES: &[&str] = &["on", "off", "true", "false", "yes", "no"];
+
+/// Command line split into positional arguments and `--name value` options.
+pub struct Args {
+    positional: Vec<String>,
+    options: Vec<(String, String)>,
+}
+
+impl Args {
+    pub fn parse(raw: impl IntoIterator<Item = String>) -> Args {
+        let mut positional = Vec::new();
+        let mut options = Vec::new();
+        let mut raw = raw.into_iter().peekable();
+
+        while let Some(arg) = raw.next() {
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    let takes_value = |next: &String| match FLAGS.contains(&name) {
+                        true => FLAG_VALUES.contains(&next.as_str()),
+                        false => !next.starts_with("--"),
+                    };
+                    let value = match raw.peek() {
+                        Some(next) if takes_value(next) => raw.next().unwrap(),
+                        _ => String::new(),
+                    };
+                    options.push((name.to_string(), value));
+                }
+                None => positional.push(arg),
+            }
+        }
+
+        Args {
+            positional,
+            options,
+        }
+    }
+
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn parse_option<T: FromStr>(&self, name: &str, default: T) -> T {
+        match self.option(name) {
+            Some(value) => value.parse().unwrap_or_else(|_| {
+                eprintln!("Invalid value '{}' for --{}.", value, name);
+                std::process::exit(1);
+            }),
+            None => default,
+        }
+    }
+
+    /// Reads an on/off option. A bare `--name` counts as on. `name` must be
+    /// listed in `FLAGS`, or `parse` lets it take the next positional.
+    pub fn flag(&self, name: &str, default: bool) -> bool {
+        debug_assert!(FLAGS.contains(&name), "--{} is missing from FLAGS", name);
+        match self.option(name) {
+            Some("" | "on" | "true" | "yes") => true,
+            Some("off" | "false" | "no") => false,
+            Some(value) => {
+                eprintln!(
+                    "Invalid value '{}' for --{}, expected on or off.",
+                    value, name
+                );
+                std::process::exit(1);
+            }
+            None => default,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bare_flags_leave_positionals_alone() {
+        let raw = "fractal --stream out.png 0 --deep 100 --progress off -1 0 1e20 --width 640";
+        let args = Args::parse(raw.split(' ').map(String::from));
+        assert_eq!(
+            args.positional(),
+            ["fractal", "out.png", "0", "100", "-1", "0", "1e20"]
+        );
+        assert!(args.flag("stream", false));
+        assert!(args.flag("deep", false));
+        assert!(!args.flag("progress", true));
+        assert_eq!(args.parse_option("width", 0), 640);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "missing from FLAGS")]
+    fn unregistered_flags_are_caught() {
+        Args::parse(["fractal".to_string()]).flag("width", false);
+    }
+}
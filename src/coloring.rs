@@ -0,0 +1,196 @@
+use rayon::prelude::*;
+
+use crate::averaging::Average;
+use crate::fractal::Escape;
+use crate::trap::TrapInput;
+
+/// How escaped orbits are turned into palette positions.
+///
+/// Rendering happens in two passes: `value` reduces each sample to a number
+/// in iteration units, then `to_positions` maps the whole frame of values to
+/// palette positions, which lets modes such as `Histogram` look at every
+/// sample before assigning colors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Coloring {
+    /// The raw integer escape count, which shows visible banding.
+    Iterations,
+    /// The continuous (normalized) iteration count.
+    Smooth,
+    /// Escape counts mapped through the frame's cumulative distribution, so
+    /// every palette band covers a similar number of pixels. With `smooth`,
+    /// positions are interpolated between neighbouring counts.
+    Histogram { smooth: bool },
+    /// Shading by the exterior distance estimate. Samples closer than
+    /// `thickness` pixels to the boundary are drawn like the interior, the
+    /// rest move through one palette pass per decade of distance.
+    Distance { thickness: f64 },
+    /// Lyapunov exponents, produced by the Lyapunov renderer rather than by
+    /// escaping orbits: stable (negative) exponents fall on the first half
+    /// of the palette, chaotic (positive) ones on the second, each side
+    /// approaching the middle as the exponent approaches zero.
+    Lyapunov,
+    /// Orbit traps, measured while iterating rather than from the escape:
+    /// the orbit's closest or mean distance to `RenderOptions::trap`, one
+    /// palette pass per unit of distance, or the iteration of its closest
+    /// approach. Interior orbits are colored too.
+    OrbitTrap { input: TrapInput },
+    /// The averaging family: a term of every orbit point averaged up to the
+    /// escape, blended between the last two iterations to stay continuous.
+    /// Averages run from 0 to 1, one palette pass.
+    Average { average: Average },
+}
+
+impl Coloring {
+    pub const NAMES: &'static [&'static str] = &[
+        "smooth",
+        "iterations",
+        "histogram",
+        "histogram-iterations",
+        "distance",
+        "trap-min",
+        "trap-average",
+        "trap-iteration",
+        "stripe",
+        "triangle",
+        "curvature",
+    ];
+
+    pub fn from_name(name: &str) -> Option<Coloring> {
+        match name {
+            "distance" => Some(Coloring::Distance { thickness: 1.0 }),
+            "smooth" => Some(Coloring::Smooth),
+            "iterations" => Some(Coloring::Iterations),
+            "histogram" => Some(Coloring::Histogram { smooth: true }),
+            "histogram-iterations" => Some(Coloring::Histogram { smooth: false }),
+            "trap-min" => Some(Coloring::OrbitTrap {
+                input: TrapInput::Min,
+            }),
+            "trap-average" => Some(Coloring::OrbitTrap {
+                input: TrapInput::Average,
+            }),
+            "trap-iteration" => Some(Coloring::OrbitTrap {
+                input: TrapInput::Iteration,
+            }),
+            "stripe" => Some(Coloring::Average {
+                average: Average::Stripe { density: 5.0 },
+            }),
+            "triangle" => Some(Coloring::Average {
+                average: Average::TriangleInequality,
+            }),
+            "curvature" => Some(Coloring::Average {
+                average: Average::Curvature,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn needs_derivative(self) -> bool {
+        matches!(self, Coloring::Distance { .. })
+    }
+
+    /// Whether `to_positions` depends on the rest of the frame, so that
+    /// colors are only known once every sample is.
+    pub fn needs_whole_frame(self) -> bool {
+        matches!(self, Coloring::Histogram { .. })
+    }
+
+    /// Whether the coloring is measured along the orbit rather than from
+    /// where it ended, so that it cannot be applied to an `Escape` alone.
+    pub fn needs_orbit(self) -> bool {
+        matches!(self, Coloring::OrbitTrap { .. } | Coloring::Average { .. })
+    }
+
+    /// First-pass value for `escape`, or `None` for interior points.
+    /// `pixel_size` is the sample spacing in complex-plane units.
+    pub fn value(self, escape: &Escape, degree: f64, pixel_size: f64) -> Option<f64> {
+        if !escape.escaped() {
+            return None;
+        }
+
+        match self {
+            Coloring::Iterations | Coloring::Histogram { smooth: false } => {
+                Some(escape.iterations as f64)
+            }
+            Coloring::Smooth | Coloring::Histogram { smooth: true } => Some(escape.smooth(degree)),
+            Coloring::Distance { thickness } => {
+                let pixels = escape.distance() / pixel_size;
+                (pixels >= thickness).then(|| (pixels / thickness).log10())
+            }
+            Coloring::Lyapunov | Coloring::OrbitTrap { .. } | Coloring::Average { .. } => None,
+        }
+    }
+
+    /// Second pass: replaces every first-pass value with its palette position.
+    /// Interior samples are stored as NaN and stay NaN.
+    pub fn to_positions(self, values: &mut [f64]) {
+        match self {
+            Coloring::Iterations
+            | Coloring::Smooth
+            | Coloring::OrbitTrap {
+                input: TrapInput::Iteration,
+            } => values
+                .par_iter_mut()
+                .for_each(|value| *value /= ITERATIONS_PER_CYCLE),
+            Coloring::Distance { .. } | Coloring::OrbitTrap { .. } | Coloring::Average { .. } => {}
+            Coloring::Lyapunov => values
+                .par_iter_mut()
+                .for_each(|value| *value = (1.0 + value.tanh()) / 2.0),
+            Coloring::Histogram { .. } => {
+                let histogram = Histogram::new(values);
+                values.par_iter_mut().for_each(|value| {
+                    if !value.is_nan() {
+                        *value = histogram.position(*value);
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Iterations covered by one pass through a palette at density 1, matching
+/// `n / 256.0` in the viewer shader.
+pub const ITERATIONS_PER_CYCLE: f64 = 256.0;
+
+/// Cumulative distribution of whole escape counts over a frame.
+struct Histogram {
+    /// Distinct counts in increasing order.
+    bins: Vec<i64>,
+    /// Number of samples with a count below each bin, with the total appended.
+    below: Vec<u64>,
+}
+
+impl Histogram {
+    fn new(values: &[f64]) -> Self {
+        let mut counts: Vec<i64> = values
+            .par_iter()
+            .filter(|value| !value.is_nan())
+            .map(|value| value.floor() as i64)
+            .collect();
+        counts.par_sort_unstable();
+
+        let mut bins = Vec::new();
+        let mut below = Vec::new();
+        for (index, &count) in counts.iter().enumerate() {
+            if bins.last() != Some(&count) {
+                bins.push(count);
+                below.push(index as u64);
+            }
+        }
+        below.push(counts.len() as u64);
+
+        Histogram { bins, below }
+    }
+
+    /// Fraction of samples escaping before `value`, interpolated across the
+    /// samples that share its whole count.
+    fn position(&self, value: f64) -> f64 {
+        let total = *self.below.last().unwrap() as f64;
+        let bin = self
+            .bins
+            .partition_point(|&count| count < value.floor() as i64);
+        let lower = self.below[bin] as f64;
+        let upper = self.below[bin + 1] as f64;
+
+        (lower + (value - value.floor()) * (upper - lower)) / total
+    }
+}
@@ -0,0 +1,104 @@
+use std::num::ParseFloatError;
+use std::ops::{Add, Mul, Sub};
+use std::str::FromStr;
+
+use crate::real::Real;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Complex<T = f64> {
+    pub re: T,
+    pub im: T,
+}
+
+impl<T: Real> Complex<T> {
+    pub const ZERO: Complex<T> = Complex {
+        re: T::ZERO,
+        im: T::ZERO,
+    };
+
+    pub const fn new(re: T, im: T) -> Self {
+        Complex { re, im }
+    }
+
+    pub fn from_f64(value: Complex) -> Self {
+        Complex::new(T::from_f64(value.re), T::from_f64(value.im))
+    }
+
+    pub fn to_f64(self) -> Complex {
+        Complex::new(self.re.to_f64(), self.im.to_f64())
+    }
+
+    pub fn norm_sqr(self) -> T {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn scale(self, factor: f64) -> Self {
+        let factor = T::from_f64(factor);
+        Complex::new(self.re * factor, self.im * factor)
+    }
+
+    pub fn conj(self) -> Self {
+        Complex::new(self.re, -self.im)
+    }
+
+    pub fn square(self) -> Self {
+        let cross = self.re * self.im;
+        Complex::new(self.re * self.re - self.im * self.im, cross + cross)
+    }
+
+    pub fn powi(self, mut exponent: u32) -> Self {
+        let mut base = self;
+        let mut result = Complex::new(T::ONE, T::ZERO);
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result * base;
+            }
+            base = base.square();
+            exponent >>= 1;
+        }
+        result
+    }
+}
+
+impl Complex {
+    pub fn norm(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+}
+
+impl<T: Real> Add for Complex<T> {
+    type Output = Complex<T>;
+
+    fn add(self, rhs: Complex<T>) -> Complex<T> {
+        Complex::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl<T: Real> Sub for Complex<T> {
+    type Output = Complex<T>;
+
+    fn sub(self, rhs: Complex<T>) -> Complex<T> {
+        Complex::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl<T: Real> Mul for Complex<T> {
+    type Output = Complex<T>;
+
+    fn mul(self, rhs: Complex<T>) -> Complex<T> {
+        Complex::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+/// Parses `re,im`, as used for `--center` on the command line.
+impl FromStr for Complex {
+    type Err = ParseFloatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (re, im) = s.split_once(',').unwrap_or((s, "0"));
+        Ok(Complex::new(re.trim().parse()?, im.trim().parse()?))
+    }
+}
@@ -0,0 +1,141 @@
+use std::ops::{Add, Mul, Neg, Sub};
+
+use crate::fixed::Fixed;
+use crate::real::Real;
+
+/// Dekker's constant `2^27 + 1`, which splits an `f64` into two halves whose
+/// products are exact.
+const SPLITTER: f64 = 134217729.0;
+
+/// Fraction limbs used when parsing, comfortably beyond the 106 bits kept.
+const PARSE_LIMBS: usize = 5;
+
+/// An unevaluated sum `hi + lo` of two `f64`s with `|lo| <= ulp(hi) / 2`,
+/// giving a 106-bit mantissa at a few times the cost of `f64`. Used for views
+/// that are too deep for `f64` but too shallow to need perturbation.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub struct DoubleDouble {
+    hi: f64,
+    lo: f64,
+}
+
+impl DoubleDouble {
+    fn from_parts(hi: f64, lo: f64) -> Self {
+        let (hi, lo) = quick_two_sum(hi, lo);
+        DoubleDouble { hi, lo }
+    }
+}
+
+/// `a + b` as a rounded sum and its exact error.
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let sum = a + b;
+    let b_virtual = sum - a;
+    let error = (a - (sum - b_virtual)) + (b - b_virtual);
+    (sum, error)
+}
+
+/// `two_sum` for `|a| >= |b|`.
+fn quick_two_sum(a: f64, b: f64) -> (f64, f64) {
+    let sum = a + b;
+    (sum, b - (sum - a))
+}
+
+fn split(a: f64) -> (f64, f64) {
+    let t = SPLITTER * a;
+    let hi = t - (t - a);
+    (hi, a - hi)
+}
+
+/// `a * b` as a rounded product and its exact error.
+fn two_prod(a: f64, b: f64) -> (f64, f64) {
+    let product = a * b;
+    let (a_hi, a_lo) = split(a);
+    let (b_hi, b_lo) = split(b);
+    let error = ((a_hi * b_hi - product) + a_hi * b_lo + a_lo * b_hi) + a_lo * b_lo;
+    (product, error)
+}
+
+impl Add for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn add(self, rhs: DoubleDouble) -> DoubleDouble {
+        let (sum, error) = two_sum(self.hi, rhs.hi);
+        let (low_sum, low_error) = two_sum(self.lo, rhs.lo);
+        let (sum, error) = quick_two_sum(sum, error + low_sum);
+        DoubleDouble::from_parts(sum, error + low_error)
+    }
+}
+
+impl Sub for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn sub(self, rhs: DoubleDouble) -> DoubleDouble {
+        self + -rhs
+    }
+}
+
+impl Mul for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn mul(self, rhs: DoubleDouble) -> DoubleDouble {
+        let (product, error) = two_prod(self.hi, rhs.hi);
+        DoubleDouble::from_parts(product, error + (self.hi * rhs.lo + self.lo * rhs.hi))
+    }
+}
+
+impl Neg for DoubleDouble {
+    type Output = DoubleDouble;
+
+    fn neg(self) -> DoubleDouble {
+        DoubleDouble {
+            hi: -self.hi,
+            lo: -self.lo,
+        }
+    }
+}
+
+impl Real for DoubleDouble {
+    const ZERO: Self = DoubleDouble { hi: 0.0, lo: 0.0 };
+    const ONE: Self = DoubleDouble { hi: 1.0, lo: 0.0 };
+    /// `2^-104`, allowing for the last bit lost to rounding in each half.
+    const EPSILON: f64 = 4.930380657631324e-32;
+
+    fn from_f64(value: f64) -> Self {
+        DoubleDouble { hi: value, lo: 0.0 }
+    }
+
+    fn to_f64(self) -> f64 {
+        self.hi + self.lo
+    }
+
+    fn abs(self) -> Self {
+        if self.hi < 0.0 { -self } else { self }
+    }
+
+    /// Splits the exact decimal value into the nearest `f64` and the `f64`
+    /// nearest to what remains.
+    fn parse_decimal(text: &str) -> Option<Self> {
+        let exact = Fixed::parse(text, PARSE_LIMBS)?;
+        let hi = exact.to_f64();
+        let lo = (&exact - &Fixed::from_f64(hi, PARSE_LIMBS)).to_f64();
+        Some(DoubleDouble::from_parts(hi, lo))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_digits_lost_by_f64() {
+        let third = DoubleDouble::parse_decimal("0.333333333333333333333333333333").unwrap();
+        let tiny = DoubleDouble::parse_decimal("1e-25").unwrap();
+        let sum = third + tiny;
+
+        // f64 would round the sum straight back to a third.
+        assert_ne!(sum, third);
+        assert!(((sum - third).to_f64() - 1e-25).abs() < 1e-31);
+        let three = DoubleDouble::from_f64(3.0);
+        assert!(((third * three).to_f64() - 1.0).abs() < 1e-29);
+    }
+}
@@ -0,0 +1,288 @@
+use std::cmp::Ordering;
+use std::ops::{Add, Mul, Sub};
+
+/// Signed arbitrary-precision fixed-point number, used for deep-zoom
+/// reference orbits.
+///
+/// The magnitude is stored big-endian in 32-bit limbs: `limbs[0]` is the
+/// integer part and the remaining limbs are the fraction, so precision is
+/// `32 * (limbs.len() - 1)` bits. Operands of an arithmetic operation must
+/// have the same number of limbs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixed {
+    negative: bool,
+    limbs: Vec<u32>,
+}
+
+impl Fixed {
+    pub fn zero(fraction_limbs: usize) -> Fixed {
+        Fixed {
+            negative: false,
+            limbs: vec![0; fraction_limbs + 1],
+        }
+    }
+
+    /// Number of 32-bit fraction limbs needed to resolve `2^resolution_log2`,
+    /// plus `guard_bits` of headroom, or `None` if that needs more than
+    /// `u32::MAX` bits.
+    pub fn limbs_for(resolution_log2: f64, guard_bits: u32) -> Option<usize> {
+        let bits = (-resolution_log2).max(0.0).ceil();
+        if resolution_log2.is_nan() || bits > u32::MAX as f64 {
+            return None;
+        }
+        let bits = (bits as u32).checked_add(guard_bits)?;
+        Some(bits.div_ceil(32) as usize)
+    }
+
+    /// Parses a decimal string such as `-0.743643887037158704752191506114774`
+    /// or `1.5e-40`, rounded towards zero to `fraction_limbs` limbs.
+    pub fn parse(text: &str, fraction_limbs: usize) -> Option<Fixed> {
+        let text = text.trim();
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let (mantissa, exponent) = match text.find(['e', 'E']) {
+            Some(index) => (&text[..index], text[index + 1..].parse::<i64>().ok()?),
+            None => (text, 0),
+        };
+        let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if integer.is_empty() && fraction.is_empty() {
+            return None;
+        }
+        if !integer
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+
+        // Move the decimal point by the exponent, after dropping leading
+        // zeros so that the first digit is significant.
+        let digits: Vec<u8> = integer
+            .bytes()
+            .chain(fraction.bytes())
+            .map(|b| b - b'0')
+            .skip_while(|&digit| digit == 0)
+            .collect();
+        let leading_zeros = integer.len() + fraction.len() - digits.len();
+        let point = (integer.len() as i64)
+            .checked_add(exponent)?
+            .checked_sub(leading_zeros as i64)?;
+        if digits.is_empty() {
+            return Some(Fixed::zero(fraction_limbs));
+        }
+        // At 10^10 and up the integer limb overflows. Far enough below 1 the
+        // value truncates to zero however many more zeros precede it, so the
+        // shift is clamped there rather than spelled out digit by digit.
+        if point > 10 {
+            return None;
+        }
+        let point = point.max(-10 * (fraction_limbs as i64 + 1));
+        let (integer_digits, fraction_digits): (Vec<u8>, Vec<u8>) = if point <= 0 {
+            let zeros = std::iter::repeat_n(0, (-point) as usize);
+            (Vec::new(), zeros.chain(digits).collect())
+        } else if point as usize >= digits.len() {
+            let zeros = std::iter::repeat_n(0, point as usize - digits.len());
+            (digits.into_iter().chain(zeros).collect(), Vec::new())
+        } else {
+            let (i, f) = digits.split_at(point as usize);
+            (i.to_vec(), f.to_vec())
+        };
+
+        let mut integer_part: u32 = 0;
+        for digit in integer_digits {
+            integer_part = integer_part.checked_mul(10)?.checked_add(digit as u32)?;
+        }
+
+        // Horner's scheme from the last digit: x = (d + x) / 10.
+        let mut limbs = vec![0u32; fraction_limbs + 1];
+        for digit in fraction_digits.into_iter().rev() {
+            limbs[0] = digit as u32;
+            let mut remainder: u64 = 0;
+            for limb in limbs.iter_mut() {
+                let value = (remainder << 32) | *limb as u64;
+                *limb = (value / 10) as u32;
+                remainder = value % 10;
+            }
+        }
+        limbs[0] = integer_part;
+
+        let mut result = Fixed { negative, limbs };
+        result.normalize_zero();
+        Some(result)
+    }
+
+    pub fn fraction_limbs(&self) -> usize {
+        self.limbs.len() - 1
+    }
+
+    /// Converts `value`, exact down to `fraction_limbs` limbs.
+    pub fn from_f64(value: f64, fraction_limbs: usize) -> Fixed {
+        let mut limbs = vec![0u32; fraction_limbs + 1];
+        let mut remaining = value.abs();
+        for limb in limbs.iter_mut() {
+            let whole = remaining.floor();
+            *limb = whole as u32;
+            remaining = (remaining - whole) * 4294967296.0;
+        }
+        let mut result = Fixed {
+            negative: value < 0.0,
+            limbs,
+        };
+        result.normalize_zero();
+        result
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let mut value = 0.0;
+        let mut scale = 1.0;
+        for &limb in &self.limbs {
+            value += limb as f64 * scale;
+            scale /= 4294967296.0;
+        }
+        if self.negative { -value } else { value }
+    }
+
+    pub fn square(&self) -> Fixed {
+        self * self
+    }
+
+    fn normalize_zero(&mut self) {
+        if self.limbs.iter().all(|&limb| limb == 0) {
+            self.negative = false;
+        }
+    }
+
+    fn compare_magnitude(&self, other: &Fixed) -> Ordering {
+        self.limbs.cmp(&other.limbs)
+    }
+
+    fn add_magnitude(&self, other: &Fixed) -> Vec<u32> {
+        let mut limbs = vec![0u32; self.limbs.len()];
+        let mut carry = 0u64;
+        for i in (0..limbs.len()).rev() {
+            let sum = self.limbs[i] as u64 + other.limbs[i] as u64 + carry;
+            limbs[i] = sum as u32;
+            carry = sum >> 32;
+        }
+        limbs
+    }
+
+    /// `|self| - |other|`, requiring `|self| >= |other|`.
+    fn sub_magnitude(&self, other: &Fixed) -> Vec<u32> {
+        let mut limbs = vec![0u32; self.limbs.len()];
+        let mut borrow = 0i64;
+        for i in (0..limbs.len()).rev() {
+            let mut difference = self.limbs[i] as i64 - other.limbs[i] as i64 - borrow;
+            borrow = 0;
+            if difference < 0 {
+                difference += 1 << 32;
+                borrow = 1;
+            }
+            limbs[i] = difference as u32;
+        }
+        limbs
+    }
+
+    fn signed_add(&self, other: &Fixed, other_negative: bool) -> Fixed {
+        debug_assert_eq!(self.limbs.len(), other.limbs.len());
+        let mut result = if self.negative == other_negative {
+            Fixed {
+                negative: self.negative,
+                limbs: self.add_magnitude(other),
+            }
+        } else if self.compare_magnitude(other) != Ordering::Less {
+            Fixed {
+                negative: self.negative,
+                limbs: self.sub_magnitude(other),
+            }
+        } else {
+            Fixed {
+                negative: other_negative,
+                limbs: other.sub_magnitude(self),
+            }
+        };
+        result.normalize_zero();
+        result
+    }
+}
+
+impl Add for &Fixed {
+    type Output = Fixed;
+
+    fn add(self, rhs: &Fixed) -> Fixed {
+        self.signed_add(rhs, rhs.negative)
+    }
+}
+
+impl Sub for &Fixed {
+    type Output = Fixed;
+
+    fn sub(self, rhs: &Fixed) -> Fixed {
+        self.signed_add(rhs, !rhs.negative)
+    }
+}
+
+impl Mul for &Fixed {
+    type Output = Fixed;
+
+    /// Schoolbook product, truncated back to the operands' precision.
+    fn mul(self, rhs: &Fixed) -> Fixed {
+        debug_assert_eq!(self.limbs.len(), rhs.limbs.len());
+        let n = self.limbs.len();
+        // product[k] holds the coefficient of 2^(-32 k); the integer limb is k = 0.
+        let mut product = vec![0u64; 2 * n];
+        for i in (0..n).rev() {
+            let a = self.limbs[i] as u64;
+            if a == 0 {
+                continue;
+            }
+            let mut carry = 0u64;
+            for j in (0..n).rev() {
+                let value = product[i + j + 1] + a * rhs.limbs[j] as u64 + carry;
+                product[i + j + 1] = value & 0xffff_ffff;
+                carry = value >> 32;
+            }
+            product[i] += carry;
+        }
+
+        // product[1] is the integer limb of the result, product[0] overflow.
+        let mut result = Fixed {
+            negative: self.negative != rhs.negative,
+            limbs: product[1..=n].iter().map(|&limb| limb as u32).collect(),
+        };
+        result.normalize_zero();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_handles_extreme_exponents() {
+        let zero = Fixed::zero(4);
+        assert_eq!(Fixed::parse("1e-999999999999", 4), Some(zero.clone()));
+        assert_eq!(Fixed::parse("-0.000e999999999999", 4), Some(zero));
+        assert_eq!(Fixed::parse("1e999999999999", 4), None);
+        assert_eq!(Fixed::parse("1e9223372036854775807", 4), None);
+        assert_eq!(
+            Fixed::parse("4294967295", 1).unwrap().to_f64(),
+            4294967295.0
+        );
+        assert_eq!(Fixed::parse("0004.25e-2", 2).unwrap().to_f64(), 0.0425_f64);
+    }
+
+    #[test]
+    fn limbs_for_refuses_unbounded_precision() {
+        assert_eq!(Fixed::limbs_for(-100.0, 64), Some(6));
+        assert_eq!(Fixed::limbs_for(3.0, 0), Some(0));
+        assert_eq!(Fixed::limbs_for(f64::NEG_INFINITY, 64), None);
+        assert_eq!(Fixed::limbs_for(f64::NAN, 64), None);
+        assert_eq!(Fixed::limbs_for(-1e15, 64), None);
+        assert_eq!(Fixed::limbs_for(-(u32::MAX as f64), 64), None);
+    }
+}
@@ -0,0 +1,210 @@
+use std::cmp::Ordering;
+use std::ops::{Add, Mul, Neg, Sub};
+
+use crate::real::Real;
+
+/// A float with an `f64` mantissa and a separate `i64` exponent, for
+/// perturbation deltas far below the range of `f64`.
+///
+/// The value is `mantissa * 2^exponent` with `0.5 <= |mantissa| < 1`, or
+/// zero with a zero exponent, so equal values have equal representations.
+/// Arithmetic rounds exactly like `f64`; only the range differs.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FloatExp {
+    mantissa: f64,
+    exponent: i64,
+}
+
+impl FloatExp {
+    pub fn new(mantissa: f64, exponent: i64) -> Self {
+        if mantissa == 0.0 || !mantissa.is_finite() {
+            return FloatExp {
+                mantissa,
+                exponent: 0,
+            };
+        }
+
+        let (mantissa, shift) = frexp(mantissa);
+        FloatExp {
+            mantissa,
+            exponent: exponent + shift,
+        }
+    }
+
+    /// `self * 2^power`.
+    pub fn mul_pow2(self, power: i64) -> Self {
+        FloatExp::new(self.mantissa, self.exponent + power)
+    }
+
+    pub fn recip(self) -> Self {
+        FloatExp::new(1.0 / self.mantissa, -self.exponent)
+    }
+
+    pub fn log2(self) -> f64 {
+        self.mantissa.abs().log2() + self.exponent as f64
+    }
+}
+
+/// Splits a finite, non-zero `value` into a mantissa in `[0.5, 1)` and a
+/// power of two.
+fn frexp(value: f64) -> (f64, i64) {
+    let bits = value.to_bits();
+    let biased = ((bits >> 52) & 0x7ff) as i64;
+    if biased == 0 {
+        // Subnormal: scale into the normal range first.
+        let (mantissa, exponent) = frexp(value * 2f64.powi(64));
+        return (mantissa, exponent - 64);
+    }
+    let mantissa = f64::from_bits((bits & !(0x7ff << 52)) | (1022 << 52));
+    (mantissa, biased - 1022)
+}
+
+/// `value * 2^power`, saturating to zero or infinity outside `f64`'s range.
+fn ldexp(mut value: f64, mut power: i64) -> f64 {
+    power = power.clamp(-2200, 2200);
+    while power > 1000 {
+        value *= 2f64.powi(1000);
+        power -= 1000;
+    }
+    while power < -1000 {
+        value *= 2f64.powi(-1000);
+        power += 1000;
+    }
+    value * 2f64.powi(power as i32)
+}
+
+impl Add for FloatExp {
+    type Output = FloatExp;
+
+    fn add(self, rhs: FloatExp) -> FloatExp {
+        if self.mantissa == 0.0 {
+            return rhs;
+        }
+        if rhs.mantissa == 0.0 {
+            return self;
+        }
+
+        let (large, small) = match self.exponent >= rhs.exponent {
+            true => (self, rhs),
+            false => (rhs, self),
+        };
+        let shift = large.exponent - small.exponent;
+        if shift > 64 {
+            return large;
+        }
+        FloatExp::new(
+            large.mantissa + ldexp(small.mantissa, -shift),
+            large.exponent,
+        )
+    }
+}
+
+impl Sub for FloatExp {
+    type Output = FloatExp;
+
+    fn sub(self, rhs: FloatExp) -> FloatExp {
+        self + -rhs
+    }
+}
+
+impl Mul for FloatExp {
+    type Output = FloatExp;
+
+    fn mul(self, rhs: FloatExp) -> FloatExp {
+        FloatExp::new(self.mantissa * rhs.mantissa, self.exponent + rhs.exponent)
+    }
+}
+
+impl Neg for FloatExp {
+    type Output = FloatExp;
+
+    fn neg(self) -> FloatExp {
+        FloatExp {
+            mantissa: -self.mantissa,
+            exponent: self.exponent,
+        }
+    }
+}
+
+impl PartialOrd for FloatExp {
+    fn partial_cmp(&self, other: &FloatExp) -> Option<Ordering> {
+        (*self - *other).mantissa.partial_cmp(&0.0)
+    }
+}
+
+impl Real for FloatExp {
+    const ZERO: Self = FloatExp {
+        mantissa: 0.0,
+        exponent: 0,
+    };
+    const ONE: Self = FloatExp {
+        mantissa: 0.5,
+        exponent: 1,
+    };
+    const EPSILON: f64 = f64::EPSILON;
+
+    fn from_f64(value: f64) -> Self {
+        FloatExp::new(value, 0)
+    }
+
+    fn to_f64(self) -> f64 {
+        ldexp(self.mantissa, self.exponent)
+    }
+
+    fn abs(self) -> Self {
+        FloatExp {
+            mantissa: self.mantissa.abs(),
+            exponent: self.exponent,
+        }
+    }
+
+    /// Parses decimals such as `2.5e-1000`, whose exponent `f64` cannot hold.
+    fn parse_decimal(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let (mantissa, exponent) = match text.find(['e', 'E']) {
+            Some(index) => (&text[..index], text[index + 1..].parse::<i64>().ok()?),
+            None => (text, 0),
+        };
+        let mantissa: f64 = mantissa.parse().ok()?;
+        if !mantissa.is_finite() {
+            return None;
+        }
+
+        // 10^|exponent| by repeated squaring.
+        let mut power = FloatExp::ONE;
+        let mut base = FloatExp::from_f64(10.0);
+        let mut remaining = exponent.unsigned_abs();
+        while remaining > 0 {
+            if remaining & 1 == 1 {
+                power = power * base;
+            }
+            base = base * base;
+            remaining >>= 1;
+        }
+        if exponent < 0 {
+            power = power.recip();
+        }
+        Some(FloatExp::from_f64(mantissa) * power)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_values_beyond_f64_range() {
+        let tiny = FloatExp::parse_decimal("3e-500").unwrap();
+        assert_eq!(tiny.to_f64(), 0.0);
+        assert!((tiny.log2() - (3.0f64.log2() - 500.0 * 10f64.log2())).abs() < 1e-9);
+
+        let product = tiny * FloatExp::parse_decimal("1e480").unwrap();
+        assert!((product.to_f64() / 3e-20 - 1.0).abs() < 1e-12);
+        assert!(tiny + tiny > tiny);
+        assert_eq!((tiny - tiny).to_f64(), 0.0);
+        assert_eq!(
+            FloatExp::from_f64(0.1) + FloatExp::from_f64(0.2),
+            FloatExp::from_f64(0.1 + 0.2)
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use crate::complex::Complex;
+use crate::real::Real;
+
+/// An escape-time iteration `z -> step(z, c)`.
+///
+/// `mandelbrot` iterates each pixel `c` starting from `initial_z(c)`, while
+/// `julia` fixes `c` and starts from the pixel itself. Iteration stops once
+/// `|z|` exceeds `bailout()`, unless the render overrides the radius.
+pub trait Formula: Sync {
+    fn step<T: Real>(&self, z: Complex<T>, c: Complex<T>) -> Complex<T>;
+
+    fn initial_z<T: Real>(&self, _c: Complex<T>) -> Complex<T> {
+        Complex::ZERO
+    }
+
+    fn bailout(&self) -> f64 {
+        2.0
+    }
+
+    /// Growth rate of `|z|` near infinity, used to normalize smooth coloring.
+    fn degree(&self) -> f64 {
+        2.0
+    }
+
+    /// `d step / dz` at `z`, used for distance estimation. Formulas that fold
+    /// `z` with `abs` or `conj` are not analytic; they use the derivative of
+    /// the unfolded polynomial, which has the right magnitude.
+    fn derivative(&self, z: Complex) -> Complex {
+        z.scale(2.0)
+    }
+
+    /// Whether parameter `c` is known to lie inside the set without iterating.
+    /// Only consulted by `mandelbrot`, where `z` starts at `initial_z(c)`.
+    fn known_interior<T: Real>(&self, _c: Complex<T>) -> bool {
+        false
+    }
+}
+
+/// The classic `z^2 + c`.
+pub struct Quadratic;
+
+impl Formula for Quadratic {
+    fn step<T: Real>(&self, z: Complex<T>, c: Complex<T>) -> Complex<T> {
+        z.square() + c
+    }
+
+    /// Main cardioid and period-2 bulb.
+    fn known_interior<T: Real>(&self, c: Complex<T>) -> bool {
+        let quarter = T::from_f64(0.25);
+        let x = c.re - quarter;
+        let q = x * x + c.im * c.im;
+        let in_cardioid = q * (q + x) <= quarter * c.im * c.im;
+        let in_bulb = (c.re + T::ONE) * (c.re + T::ONE) + c.im * c.im <= T::from_f64(0.0625);
+        in_cardioid || in_bulb
+    }
+}
+
+/// `(|Re z| + i|Im z|)^2 + c`.
+pub struct BurningShip;
+
+impl Formula for BurningShip {
+    fn step<T: Real>(&self, z: Complex<T>, c: Complex<T>) -> Complex<T> {
+        Complex::new(z.re.abs(), z.im.abs()).square() + c
+    }
+}
+
+/// The Mandelbar, `conj(z)^2 + c`.
+pub struct Tricorn;
+
+impl Formula for Tricorn {
+    fn step<T: Real>(&self, z: Complex<T>, c: Complex<T>) -> Complex<T> {
+        z.conj().square() + c
+    }
+}
+
+/// `|Re z^2| + i Im z^2 + c`.
+pub struct Celtic;
+
+impl Formula for Celtic {
+    fn step<T: Real>(&self, z: Complex<T>, c: Complex<T>) -> Complex<T> {
+        let z2 = z.square();
+        Complex::new(z2.re.abs(), z2.im) + c
+    }
+}
+
+/// `|Re z^2| + i|Im z^2| + c`.
+pub struct Buffalo;
+
+impl Formula for Buffalo {
+    fn step<T: Real>(&self, z: Complex<T>, c: Complex<T>) -> Complex<T> {
+        let z2 = z.square();
+        Complex::new(z2.re.abs(), z2.im.abs()) + c
+    }
+}
+
+/// `z^power + c` for an integer power of at least 2.
+pub struct Multibrot {
+    pub power: u32,
+}
+
+impl Formula for Multibrot {
+    fn step<T: Real>(&self, z: Complex<T>, c: Complex<T>) -> Complex<T> {
+        z.powi(self.power) + c
+    }
+
+    fn degree(&self) -> f64 {
+        self.power as f64
+    }
+
+    fn derivative(&self, z: Complex) -> Complex {
+        z.powi(self.power - 1).scale(self.power as f64)
+    }
+}
+
+/// Runtime selection between the built-in formulas, used by the CLI.
+pub enum FormulaKind {
+    Quadratic(Quadratic),
+    BurningShip(BurningShip),
+    Tricorn(Tricorn),
+    Celtic(Celtic),
+    Buffalo(Buffalo),
+    Multibrot(Multibrot),
+}
+
+impl FormulaKind {
+    pub const NAMES: &'static [&'static str] = &[
+        "mandelbrot",
+        "burning-ship",
+        "tricorn",
+        "celtic",
+        "buffalo",
+        "multibrot",
+    ];
+
+    pub fn from_name(name: &str, power: u32) -> Option<FormulaKind> {
+        match name {
+            "mandelbrot" | "quadratic" => Some(FormulaKind::Quadratic(Quadratic)),
+            "burning-ship" => Some(FormulaKind::BurningShip(BurningShip)),
+            "tricorn" | "mandelbar" => Some(FormulaKind::Tricorn(Tricorn)),
+            "celtic" => Some(FormulaKind::Celtic(Celtic)),
+            "buffalo" => Some(FormulaKind::Buffalo(Buffalo)),
+            "multibrot" => Some(FormulaKind::Multibrot(Multibrot { power })),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! dispatch {
+    ($kind:expr, $f:ident => $body:expr) => {
+        match $kind {
+            FormulaKind::Quadratic($f) => $body,
+            FormulaKind::BurningShip($f) => $body,
+            FormulaKind::Tricorn($f) => $body,
+            FormulaKind::Celtic($f) => $body,
+            FormulaKind::Buffalo($f) => $body,
+            FormulaKind::Multibrot($f) => $body,
+        }
+    };
+}
+
+impl Formula for FormulaKind {
+    fn step<T: Real>(&self, z: Complex<T>, c: Complex<T>) -> Complex<T> {
+        dispatch!(self, f => f.step(z, c))
+    }
+
+    fn initial_z<T: Real>(&self, c: Complex<T>) -> Complex<T> {
+        dispatch!(self, f => f.initial_z(c))
+    }
+
+    fn bailout(&self) -> f64 {
+        dispatch!(self, f => f.bailout())
+    }
+
+    fn degree(&self) -> f64 {
+        dispatch!(self, f => f.degree())
+    }
+
+    fn derivative(&self, z: Complex) -> Complex {
+        dispatch!(self, f => f.derivative(z))
+    }
+
+    fn known_interior<T: Real>(&self, c: Complex<T>) -> bool {
+        dispatch!(self, f => f.known_interior(c))
+    }
+}
@@ -1,87 +1,1355 @@
+use std::fmt;
+use std::ops::Add;
+use std::sync::mpsc::Sender;
+
 use image::{ImageBuffer, Rgb, RgbImage};
 use rayon::prelude::*;
 
-const IMAGE_W: f64 = 4096.0;
-const IMAGE_H: f64 = 2160.0;
+use crate::averaging::{Average, AverageVisitor};
+use crate::coloring::Coloring;
+use crate::complex::Complex;
+use crate::formula::Formula;
+use crate::palette::{self, Palette};
+use crate::progress::{Progress, Reporter};
+use crate::raw::RawData;
+use crate::real::Real;
+use crate::sampling::Sampling;
+use crate::subdivision::{self, Subdivision};
+use crate::tiles::{self, CancelToken, Tile, TileOrder};
+use crate::trap::{OrbitVisitor, Trap, TrapVisitor};
+use crate::viewport::{Frame, Viewport};
 
-pub fn mandelbrot(
-    max_recursion: u32,
-    real_center: f64,
-    imaginary_center: f64,
-    zoom_factor: f64,
-) -> RgbImage {
-    let mut img = ImageBuffer::new(IMAGE_W as u32, IMAGE_H as u32);
+pub const DEFAULT_WIDTH: u32 = 4096;
+pub const DEFAULT_HEIGHT: u32 = 2160;
+pub const DEFAULT_TILE_SIZE: u32 = 64;
 
-    let scale = 2.0 / IMAGE_H as f64;
-    let max_iterations = max_recursion as usize;
+const INTERIOR: [f64; 3] = [0.0, 0.0, 0.0];
 
-    img.par_enumerate_pixels_mut()
-        .for_each(|(img_x, img_y, pixel)| {
-            let x_pixel = img_x as f64 - (IMAGE_W as f64 / 2.0);
-            let y_pixel = (IMAGE_H as f64 / 2.0) - 1.0 - img_y as f64;
+/// Pixel spacings of the passes of a progressive render, coarsest first.
+const PROGRESSIVE_STEPS: [usize; 3] = [4, 2, 1];
 
-            let re_c = real_center + (x_pixel / zoom_factor) * scale;
-            let im_c = imaginary_center + (y_pixel / zoom_factor) * scale;
+/// Per-render settings shared by `mandelbrot` and `julia`.
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub max_iterations: u64,
+    /// Samples per pixel along each axis.
+    pub supersample: u32,
+    /// How the `supersample`² samples of each pixel are placed.
+    pub sampling: Sampling,
+    /// Escape radius; `None` uses the formula's own bailout.
+    pub bailout: Option<f64>,
+    pub coloring: Coloring,
+    pub palette: Palette,
+    /// Short-circuit interior points with the cardioid/bulb test and orbit
+    /// periodicity detection. Does not change the image.
+    pub interior_checks: bool,
+    /// Let deep renders start every pixel from a series approximation of the
+    /// reference orbit instead of iteration zero. Does not visibly change the
+    /// image.
+    pub series_approximation: bool,
+    /// Fill rectangles whose border shares one value instead of iterating
+    /// their inside. Only applies to grid sampling.
+    pub subdivision: Subdivision,
+    /// Receives progress while rendering. Grid-sampled renders without
+    /// subdivision then run coarse to fine, sending a preview after each of
+    /// the 1/16 and 1/4 resolution passes.
+    pub progress: Option<Sender<Progress>>,
+    /// Side of the square tiles the image is rendered in, in pixels.
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    /// Stops the render between tiles once set.
+    pub cancel: CancelToken,
+    /// Renders only this rectangle of the view, at the view's pixel size,
+    /// into an image of the rectangle's size. Colorings that need the whole
+    /// frame only see the rectangle.
+    pub crop: Option<Tile>,
+    /// Receives the escape data of every sample when the render finishes.
+    /// Subdivision is then skipped and adaptive sampling supersamples every
+    /// pixel, so that each sample is computed.
+    pub raw: Option<Sender<RawData>>,
+    /// The trap orbits are measured against for `Coloring::OrbitTrap`.
+    pub trap: Trap,
+}
 
-            let depth = recursive_fractal_sequence(re_c, im_c, 0.0, 0.0, 0, max_iterations);
+impl RenderOptions {
+    pub fn new(max_iterations: u64) -> Self {
+        RenderOptions {
+            max_iterations,
+            supersample: 1,
+            sampling: Sampling::Grid,
+            bailout: None,
+            coloring: Coloring::Smooth,
+            palette: Palette::builtin("rainbow").unwrap(),
+            interior_checks: true,
+            series_approximation: true,
+            subdivision: Subdivision::Off,
+            progress: None,
+            tile_size: DEFAULT_TILE_SIZE,
+            tile_order: TileOrder::CenterOut,
+            cancel: CancelToken::new(),
+            crop: None,
+            raw: None,
+            trap: Trap::default(),
+        }
+    }
+}
 
-            let color = if depth >= max_iterations {
-                Rgb([255, 255, 255])
-            } else {
-                let alpha = depth as f64 / max_iterations as f64;
-                Rgb([0, (alpha * 255.0) as u8, (alpha * 153.0) as u8])
-            };
+/// Renders a fixed view drawn at a given frame, or the part of it that the
+/// options crop it to.
+pub type ViewRender<'a> = dyn Fn(Frame, &RenderOptions) -> (RgbImage, RenderStats) + Sync + 'a;
 
-            *pixel = color;
-        });
+/// Counters collected while rendering.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RenderStats {
+    pub samples: u64,
+    pub interior: u64,
+    /// Interior samples resolved by `Formula::known_interior` without iterating.
+    pub known_interior: u64,
+    /// Interior samples whose orbit was found to be periodic before
+    /// `max_iterations`.
+    pub periodic: u64,
+    /// Length of the perturbation reference orbit, for deep renders.
+    pub reference_iterations: u64,
+    /// Perturbed orbits moved back to the start of the reference because
+    /// they came closer to zero than their delta or outlived the reference.
+    pub rebases: u64,
+    /// Rebases triggered by the glitch criterion.
+    pub glitches: u64,
+    /// Iterations skipped by the series approximation, summed over samples.
+    pub skipped: u64,
+    /// Pixels that adaptive sampling supersampled.
+    pub refined: u64,
+    /// Samples filled by rectangle subdivision without iterating.
+    pub filled: u64,
+    /// Whether the render was cancelled before it finished.
+    pub cancelled: bool,
+}
 
-    img
+impl RenderStats {
+    pub fn record(&mut self, exit: Exit) {
+        self.samples += 1;
+        match exit {
+            Exit::Escaped => {}
+            Exit::MaxIterations => self.interior += 1,
+            Exit::KnownInterior => {
+                self.interior += 1;
+                self.known_interior += 1;
+            }
+            Exit::Periodic => {
+                self.interior += 1;
+                self.periodic += 1;
+            }
+        }
+    }
+}
+
+impl Add for RenderStats {
+    type Output = RenderStats;
+
+    fn add(self, rhs: RenderStats) -> RenderStats {
+        RenderStats {
+            samples: self.samples + rhs.samples,
+            interior: self.interior + rhs.interior,
+            known_interior: self.known_interior + rhs.known_interior,
+            periodic: self.periodic + rhs.periodic,
+            reference_iterations: self.reference_iterations.max(rhs.reference_iterations),
+            rebases: self.rebases + rhs.rebases,
+            glitches: self.glitches + rhs.glitches,
+            skipped: self.skipped + rhs.skipped,
+            refined: self.refined + rhs.refined,
+            filled: self.filled + rhs.filled,
+            cancelled: self.cancelled || rhs.cancelled,
+        }
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} samples, {} interior ({} by cardioid/bulb test, {} by periodicity)",
+            self.samples, self.interior, self.known_interior, self.periodic
+        )?;
+        if self.refined > 0 {
+            write!(f, ", {} pixels refined", self.refined)?;
+        }
+        if self.filled > 0 {
+            write!(f, ", {} samples filled by subdivision", self.filled)?;
+        }
+        if self.cancelled {
+            write!(f, ", cancelled")?;
+        }
+        if self.reference_iterations > 0 {
+            write!(
+                f,
+                ", reference orbit of {} iterations, {} rebases ({} glitches), {} iterations skipped by series approximation",
+                self.reference_iterations, self.rebases, self.glitches, self.skipped
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Which derivative `escape` tracks alongside the orbit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Derivative {
+    None,
+    /// `dz/dc`, for the parameter plane rendered by `mandelbrot`.
+    Parameter,
+    /// `dz/dz0`, for the dynamic plane rendered by `julia`.
+    Dynamic,
 }
-pub fn julia(max_recursion: u32, re_c: f64, im_c: f64) -> RgbImage {
-    let mut img = ImageBuffer::new(IMAGE_W as u32, IMAGE_H as u32);
 
-    let scale = 3.0 / IMAGE_H as f64;
-    let max_iterations = max_recursion as usize;
+/// Why the escape loop stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    Escaped,
+    MaxIterations,
+    /// Skipped by `Formula::known_interior`.
+    KnownInterior,
+    /// The orbit returned to a previously saved point.
+    Periodic,
+}
+
+/// Where an orbit ended up: the number of steps taken, the last `z`, and
+/// why iteration stopped. Interior exits report `max_iterations` steps.
+/// `derivative` is only tracked when requested and is zero otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct Escape {
+    pub iterations: u64,
+    pub z: Complex,
+    pub derivative: Complex,
+    pub exit: Exit,
+}
+
+impl Escape {
+    pub fn escaped(&self) -> bool {
+        self.exit == Exit::Escaped
+    }
+
+    /// Continuous iteration count for a formula of `degree`, using the same
+    /// log-log normalization as `mandelbrot_smooth` in `shader.wgsl`. Larger
+    /// bailout radii make it smoother without shifting its value.
+    pub fn smooth(&self, degree: f64) -> f64 {
+        let log_z = self.z.norm_sqr().ln() / 2.0;
+        let nu = (log_z / std::f64::consts::LN_2).ln() / degree.ln();
+        self.iterations as f64 + 1.0 - nu
+    }
+
+    /// Exterior distance estimate `|z| ln|z| / |dz|` to the fractal boundary,
+    /// in complex-plane units.
+    pub fn distance(&self) -> f64 {
+        let norm = self.z.norm();
+        norm * norm.ln() / self.derivative.norm()
+    }
+}
+
+/// Per-render settings for the escape loop, resolved once from
+/// `RenderOptions`.
+#[derive(Debug, Clone, Copy)]
+pub struct Kernel {
+    pub max_iterations: u64,
+    pub bailout: f64,
+    pub derivative: Derivative,
+    detect_periods: bool,
+}
+
+impl Kernel {
+    pub fn new<F: Formula>(formula: &F, options: &RenderOptions, plane: Derivative) -> Self {
+        Kernel {
+            max_iterations: options.max_iterations,
+            bailout: options.bailout.unwrap_or(formula.bailout()),
+            derivative: match options.coloring.needs_derivative() || options.raw.is_some() {
+                true => plane,
+                false => Derivative::None,
+            },
+            detect_periods: options.interior_checks,
+        }
+    }
+
+    fn interior<T: Real>(&self, z: Complex<T>, exit: Exit) -> Escape {
+        Escape {
+            iterations: self.max_iterations,
+            z: z.to_f64(),
+            derivative: Complex::ZERO,
+            exit,
+        }
+    }
+
+    /// Iterates `formula` from `z` until `|z|` exceeds the bailout, the orbit
+    /// is found to be periodic, or `max_iterations` steps have been taken.
+    ///
+    /// Periodicity uses Brent's method: `z` is compared against a saved point
+    /// that is refreshed after 1, 2, 4, 8, ... steps, so a cycle of any length
+    /// is caught within a few times its period once the orbit has settled.
+    /// The match tolerance tightens with the precision of `T`, but is never
+    /// looser than for `f64`: at `f32` that only matches exact repeats, as a
+    /// scaled tolerance would catch slowly escaping orbits.
+    fn escape<F: Formula, T: Real>(&self, formula: &F, c: Complex<T>, z: Complex<T>) -> Escape {
+        self.escape_visited(formula, c, z, &mut ())
+    }
 
-    img.par_enumerate_pixels_mut()
-        .for_each(|(img_x, img_y, pixel)| {
-            let x_pixel_int = img_x as i32 - IMAGE_W as i32 / 2;
-            let y_pixel_int = IMAGE_H as i32 / 2 - 1 - img_y as i32;
+    /// `escape`, showing `visitor` every point of the orbit.
+    fn escape_visited<F: Formula, T: Real, V: OrbitVisitor>(
+        &self,
+        formula: &F,
+        c: Complex<T>,
+        mut z: Complex<T>,
+        visitor: &mut V,
+    ) -> Escape {
+        let bailout_sqr = self.bailout * self.bailout;
+        let period_tolerance_sqr =
+            PERIOD_TOLERANCE_SQR * (T::EPSILON / f64::EPSILON).min(1.0).powi(2);
+        let (mut dz, dc) = match self.derivative {
+            Derivative::None | Derivative::Parameter => (Complex::ZERO, Complex::new(1.0, 0.0)),
+            Derivative::Dynamic => (Complex::new(1.0, 0.0), Complex::ZERO),
+        };
+        let mut saved = z;
+        let mut saved_age = 0u64;
+        let mut saved_interval = 1u64;
 
-            let x_pixel = x_pixel_int as f64;
-            let y_pixel = y_pixel_int as f64;
+        for iterations in 0..self.max_iterations {
+            if z.norm_sqr().to_f64() > bailout_sqr {
+                return Escape {
+                    iterations,
+                    z: z.to_f64(),
+                    derivative: dz,
+                    exit: Exit::Escaped,
+                };
+            }
+            if iterations > 0 {
+                visitor.visit(iterations, z.to_f64());
+            }
+            if self.derivative != Derivative::None {
+                dz = formula.derivative(z.to_f64()) * dz + dc;
+            }
+            z = formula.step(z, c);
 
-            let re_z = x_pixel * scale;
-            let im_z = y_pixel * scale;
-            let depth = recursive_fractal_sequence(re_c, im_c, re_z, im_z, 0, max_iterations);
+            if self.detect_periods {
+                if (z - saved).norm_sqr().to_f64() < period_tolerance_sqr {
+                    return self.interior(z, Exit::Periodic);
+                }
+                saved_age += 1;
+                if saved_age == saved_interval {
+                    saved = z;
+                    saved_age = 0;
+                    saved_interval *= 2;
+                }
+            }
+        }
 
-            let (red, green, blue) = if depth >= max_iterations {
-                (255, 255, 255)
-            } else {
-                let alpha = depth as f64 / max_iterations as f64;
-                (0, (alpha * 255.0) as u8, (alpha * 153.0) as u8)
+        Escape {
+            iterations: self.max_iterations,
+            z: z.to_f64(),
+            derivative: dz,
+            exit: Exit::MaxIterations,
+        }
+    }
+
+    /// The orbit trap `input` of the orbit from `z`, NaN if the orbit
+    /// escapes before visiting a point. Periodicity detection is skipped so
+    /// that interior orbits are followed all the way.
+    fn trapped<F: Formula, T: Real>(
+        &self,
+        formula: &F,
+        c: Complex<T>,
+        z: Complex<T>,
+        options: &RenderOptions,
+        stats: &mut RenderStats,
+    ) -> f64 {
+        let Coloring::OrbitTrap { input } = options.coloring else {
+            unreachable!("trapped without an orbit trap coloring");
+        };
+        let kernel = Kernel {
+            detect_periods: false,
+            ..*self
+        };
+        let mut visitor = TrapVisitor::new(&options.trap);
+        stats.record(kernel.escape_visited(formula, c, z, &mut visitor).exit);
+        visitor.value(input)
+    }
+
+    /// The `average` of the orbit from `z`, NaN for the interior.
+    fn averaged<F: Formula, T: Real>(
+        &self,
+        formula: &F,
+        c: Complex<T>,
+        z: Complex<T>,
+        average: Average,
+        stats: &mut RenderStats,
+    ) -> f64 {
+        let mut visitor = AverageVisitor::new(average, c.to_f64(), z.to_f64(), formula.degree());
+        let escape = self.escape_visited(formula, c, z, &mut visitor);
+        stats.record(escape.exit);
+        visitor.value(&escape, self.bailout)
+    }
+}
+
+/// Squared distance below which an `f64` orbit is treated as having returned
+/// to a saved point. Tight enough that only fully settled cycles match, so slowly
+/// escaping orbits near the boundary are never misclassified.
+const PERIOD_TOLERANCE_SQR: f64 = 1.0e-24;
+
+pub fn mandelbrot<F: Formula, T: Real>(
+    formula: &F,
+    viewport: &Viewport<T>,
+    options: &RenderOptions,
+) -> (RgbImage, RenderStats) {
+    let kernel = Kernel::new(formula, options, Derivative::Parameter);
+    match options.coloring {
+        Coloring::OrbitTrap { .. } => {
+            return render_values(viewport, options, |offset, stats| {
+                let c = viewport.center + Complex::from_f64(offset);
+                kernel.trapped(formula, c, formula.initial_z(c), options, stats)
+            });
+        }
+        Coloring::Average { average } => {
+            return render_values(viewport, options, |offset, stats| {
+                let c = viewport.center + Complex::from_f64(offset);
+                if options.interior_checks && formula.known_interior(c) {
+                    stats.record(Exit::KnownInterior);
+                    return f64::NAN;
+                }
+                kernel.averaged(formula, c, formula.initial_z(c), average, stats)
+            });
+        }
+        _ => {}
+    }
+
+    render(viewport, options, formula.degree(), |offset| {
+        let c = viewport.center + Complex::from_f64(offset);
+        let z = formula.initial_z(c);
+        if options.interior_checks && formula.known_interior(c) {
+            return kernel.interior(z, Exit::KnownInterior);
+        }
+        kernel.escape(formula, c, z)
+    })
+}
+
+pub fn julia<F: Formula, T: Real>(
+    formula: &F,
+    viewport: &Viewport<T>,
+    options: &RenderOptions,
+    c: Complex,
+) -> (RgbImage, RenderStats) {
+    let kernel = Kernel::new(formula, options, Derivative::Dynamic);
+    let c = Complex::from_f64(c);
+    match options.coloring {
+        Coloring::OrbitTrap { .. } => {
+            return render_values(viewport, options, |offset, stats| {
+                let z = viewport.center + Complex::from_f64(offset);
+                kernel.trapped(formula, c, z, options, stats)
+            });
+        }
+        Coloring::Average { average } => {
+            return render_values(viewport, options, |offset, stats| {
+                let z = viewport.center + Complex::from_f64(offset);
+                kernel.averaged(formula, c, z, average, stats)
+            });
+        }
+        _ => {}
+    }
+
+    render(viewport, options, formula.degree(), |offset| {
+        kernel.escape(formula, c, viewport.center + Complex::from_f64(offset))
+    })
+}
+
+/// Evaluates `sample` at `supersample`² points inside every pixel of
+/// `viewport`, placed according to `options.sampling`, converts the whole
+/// frame of values to palette positions, then averages the resulting colors
+/// per pixel in linear light. `sample` receives each point as an offset from
+/// `viewport.center`.
+///
+/// Pixels are evaluated tile by tile in `options.tile_order`. Once
+/// `options.cancel` is set no further tiles are started, and the render
+/// returns a black image with `RenderStats::cancelled` set.
+pub fn render<T: Real, S>(
+    viewport: &Viewport<T>,
+    options: &RenderOptions,
+    degree: f64,
+    sample: S,
+) -> (RgbImage, RenderStats)
+where
+    S: Fn(Complex) -> Escape + Sync,
+{
+    if let Some(raw) = &options.raw {
+        return capture(viewport, options, degree, sample, raw);
+    }
+    let pixel_size = viewport.pixel_size();
+    render_values(viewport, options, |offset, stats| {
+        let escape = sample(offset);
+        stats.record(escape.exit);
+        options
+            .coloring
+            .value(&escape, degree, pixel_size)
+            .unwrap_or(f64::NAN)
+    })
+}
+
+/// The rectangle of `viewport` that `options` render.
+fn render_area<T: Real>(viewport: &Viewport<T>, options: &RenderOptions) -> Tile {
+    options.crop.unwrap_or(Tile {
+        x: 0,
+        y: 0,
+        width: viewport.width,
+        height: viewport.height,
+    })
+}
+
+fn cancelled(area: Tile) -> (RgbImage, RenderStats) {
+    let stats = RenderStats {
+        cancelled: true,
+        ..RenderStats::default()
+    };
+    (ImageBuffer::new(area.width, area.height), stats)
+}
+
+/// `render` for `options.raw`: keeps every sample's escape, colors the image
+/// from them and sends them to `raw`.
+fn capture<T: Real, S>(
+    viewport: &Viewport<T>,
+    options: &RenderOptions,
+    degree: f64,
+    sample: S,
+    raw: &Sender<RawData>,
+) -> (RgbImage, RenderStats)
+where
+    S: Fn(Complex) -> Escape + Sync,
+{
+    let n = options.supersample.max(1);
+    let per_pixel = (n * n) as usize;
+    let area = render_area(viewport, options);
+    let pixels = area.width as usize * area.height as usize;
+    let tiles = tiles::layout(
+        area.width,
+        area.height,
+        options.tile_size,
+        options.tile_order,
+    );
+    let reporter = Reporter::new(options.progress.as_ref(), pixels as u64);
+    let Some(results) = tiles::run(&tiles, &options.cancel, |tile| {
+        let mut stats = RenderStats::default();
+        let mut escapes = Vec::with_capacity((tile.width * tile.height) as usize * per_pixel);
+        for index in tile.pixels(area.width) {
+            let x = area.x + (index % area.width as usize) as u32;
+            let y = area.y + (index / area.width as usize) as u32;
+            for k in 0..n * n {
+                let (sx, sy) = options.sampling.position(n, x, y, k);
+                let escape = sample(viewport.pixel_offset(x as f64 + sx, y as f64 + sy));
+                stats.record(escape.exit);
+                escapes.push(escape);
+            }
+        }
+        reporter.finish_tile(*tile, (tile.width * tile.height) as u64, None);
+        (escapes, stats)
+    }) else {
+        return cancelled(area);
+    };
+
+    let unset = Escape {
+        iterations: 0,
+        z: Complex::ZERO,
+        derivative: Complex::ZERO,
+        exit: Exit::MaxIterations,
+    };
+    let mut escapes = vec![unset; pixels * per_pixel];
+    let mut stats = RenderStats::default();
+    for (tile, (tile_escapes, tile_stats)) in tiles.iter().zip(results) {
+        for (index, samples) in tile.pixels(area.width).zip(tile_escapes.chunks(per_pixel)) {
+            escapes[index * per_pixel..(index + 1) * per_pixel].copy_from_slice(samples);
+        }
+        stats = stats + tile_stats;
+    }
+    let data = RawData {
+        width: area.width,
+        height: area.height,
+        supersample: n,
+        max_iterations: options.max_iterations,
+        degree,
+        pixel_size: viewport.pixel_size(),
+        escapes,
+    };
+    let img = recolor(&data, options);
+    let _ = raw.send(data);
+    (img, stats)
+}
+
+/// The sampling, tiling and coloring behind `render`, for any kind of
+/// per-sample value. `value` receives each sample's offset from
+/// `viewport.center` and returns its first-pass value for
+/// `options.coloring`, NaN for the interior.
+pub fn render_values<T: Real, V>(
+    viewport: &Viewport<T>,
+    options: &RenderOptions,
+    value: V,
+) -> (RgbImage, RenderStats)
+where
+    V: Fn(Complex, &mut RenderStats) -> f64 + Sync,
+{
+    let n = options.supersample.max(1);
+    let area = render_area(viewport, options);
+    let width = area.width as usize;
+    let pixels = width * area.height as usize;
+    // Pixel coordinates within the whole view, so that cropped renders
+    // sample exactly where the full render would.
+    let pixel_at = |index: usize| {
+        let (x, y) = (index % width, index / width);
+        (area.x + x as u32, area.y + y as u32)
+    };
+    let evaluate =
+        |x: f64, y: f64, stats: &mut RenderStats| value(viewport.pixel_offset(x, y), stats);
+    let tiles = tiles::layout(
+        area.width,
+        area.height,
+        options.tile_size,
+        options.tile_order,
+    );
+    let cancelled = || cancelled(area);
+
+    let threshold = match options.sampling {
+        Sampling::Adaptive { threshold } if n > 1 => threshold,
+        _ => {
+            let per_pixel = (n * n) as usize;
+            let sampled = match options.subdivision {
+                Subdivision::On | Subdivision::Guarded if options.sampling == Sampling::Grid => {
+                    subdivide(area, options, &tiles, evaluate)
+                }
+                _ => {
+                    let steps: &[usize] = match options.progress {
+                        Some(_) => &PROGRESSIVE_STEPS,
+                        None => &[1],
+                    };
+                    sample_progressively(area, options, &tiles, steps, |index, sample, stats| {
+                        let (x, y) = pixel_at(index);
+                        let (sx, sy) = options.sampling.position(n, x, y, sample as u32);
+                        evaluate(x as f64 + sx, y as f64 + sy, stats)
+                    })
+                }
+            };
+            let Some((mut values, stats)) = sampled else {
+                return cancelled();
             };
+            options.coloring.to_positions(&mut values);
+
+            let mut img = ImageBuffer::new(area.width, area.height);
+            img.par_enumerate_pixels_mut().for_each(|(x, y, pixel)| {
+                let index = y as usize * width + x as usize;
+                let samples = &values[index * per_pixel..(index + 1) * per_pixel];
+                *pixel = encode(average_color(options, samples));
+            });
+            return (img, stats);
+        }
+    };
+
+    // Adaptive: one sample per pixel, then refine where neighbours differ.
+    let reporter = Reporter::new(options.progress.as_ref(), pixels as u64);
+    let finish = |tile: &Tile, slots: &[usize], _: &[f64]| {
+        reporter.finish_tile(*tile, slots.len() as u64, None);
+    };
+    let mut base = vec![0.0; pixels];
+    let Some(mut stats) = sample_tiles(
+        &tiles,
+        width,
+        1,
+        &options.cancel,
+        &mut base,
+        Some,
+        &finish,
+        |index, _, stats| {
+            let (x, y) = pixel_at(index);
+            evaluate(x as f64 + 0.5, y as f64 + 0.5, stats)
+        },
+    ) else {
+        return cancelled();
+    };
+    let mut preview = base.clone();
+    options.coloring.to_positions(&mut preview);
+    let preview: Vec<[f64; 3]> = preview
+        .par_iter()
+        .map(|&position| average_color(options, &[position]).map(palette::linear_to_srgb))
+        .collect();
 
-            *pixel = Rgb([red, green, blue]);
-        });
+    let height = area.height as usize;
+    let refine: Vec<usize> = (0..pixels)
+        .into_par_iter()
+        .filter(|&index| {
+            let (x, y) = (index % width, index / width);
+            let neighbours = (y.saturating_sub(1)..(y + 2).min(height))
+                .flat_map(|ny| (x.saturating_sub(1)..(x + 2).min(width)).map(move |nx| (nx, ny)));
+            neighbours.into_iter().any(|(nx, ny)| {
+                let other = preview[ny * width + nx];
+                preview[index]
+                    .iter()
+                    .zip(other)
+                    .any(|(a, b)| (a - b).abs() > threshold)
+            })
+        })
+        .collect();
+
+    let per_pixel = (n * n) as usize;
+    reporter.grow(refine.len() as u64);
+    let mut refined = vec![0.0; refine.len() * per_pixel];
+    let Some(refined_stats) = sample_tiles(
+        &tiles,
+        width,
+        per_pixel,
+        &options.cancel,
+        &mut refined,
+        |index| refine.binary_search(&index).ok(),
+        &finish,
+        |index, sample, stats| {
+            let (x, y) = pixel_at(index);
+            let (sx, sy) = options.sampling.position(n, x, y, sample as u32);
+            evaluate(x as f64 + sx, y as f64 + sy, stats)
+        },
+    ) else {
+        return cancelled();
+    };
+    stats = stats + refined_stats;
+    stats.refined = refine.len() as u64;
+
+    let mut values = base;
+    values.extend(refined);
+    options.coloring.to_positions(&mut values);
+    let (base, refined) = values.split_at(pixels);
+
+    let mut img = ImageBuffer::new(area.width, area.height);
+    img.par_enumerate_pixels_mut().for_each(|(x, y, pixel)| {
+        let index = y as usize * width + x as usize;
+        let samples = match refine.binary_search(&index) {
+            Ok(slot) => &refined[slot * per_pixel..(slot + 1) * per_pixel],
+            Err(_) => &base[index..index + 1],
+        };
+        *pixel = encode(average_color(options, samples));
+    });
+    (img, stats)
+}
 
+/// Evaluates `per_pixel` samples for each pixel of `tiles` that `slot` maps
+/// to a slot, and stores them at `values[slot * per_pixel..]`. `evaluate`
+/// receives the pixel's index and the sample index within it, and
+/// `on_tile` each finished tile with the indices of the pixels it computed
+/// and their samples. Returns `None` if the render was cancelled.
+#[allow(clippy::too_many_arguments)]
+fn sample_tiles<F, D, E>(
+    tiles: &[Tile],
+    width: usize,
+    per_pixel: usize,
+    cancel: &CancelToken,
+    values: &mut [f64],
+    slot: F,
+    on_tile: &D,
+    evaluate: E,
+) -> Option<RenderStats>
+where
+    F: Fn(usize) -> Option<usize> + Sync,
+    D: Fn(&Tile, &[usize], &[f64]) + Sync,
+    E: Fn(usize, usize, &mut RenderStats) -> f64 + Sync,
+{
+    let results = tiles::run(tiles, cancel, |tile| {
+        let mut stats = RenderStats::default();
+        let mut indices = Vec::new();
+        let mut slots = Vec::new();
+        let mut samples = Vec::new();
+        for index in tile.pixels(width as u32) {
+            if let Some(slot) = slot(index) {
+                indices.push(index);
+                slots.push(slot);
+                samples.extend((0..per_pixel).map(|sample| evaluate(index, sample, &mut stats)));
+            }
+        }
+        on_tile(tile, &indices, &samples);
+        (slots, samples, stats)
+    })?;
+
+    let mut stats = RenderStats::default();
+    for (slots, samples, tile_stats) in results {
+        for (&slot, samples) in slots.iter().zip(samples.chunks(per_pixel)) {
+            values[slot * per_pixel..(slot + 1) * per_pixel].copy_from_slice(samples);
+        }
+        stats = stats + tile_stats;
+    }
+    Some(stats)
+}
+
+/// First-pass values for every pixel of `area`, laid out pixel by pixel.
+/// Each of `steps` computes the pixels on a grid of that spacing which
+/// earlier, coarser passes have not, and all but the last send a preview of
+/// the pixels so far. Unless the coloring needs the whole frame, the last
+/// pass also sends the final colors of each tile as it finishes.
+/// `evaluate` receives the pixel's index.
+fn sample_progressively<E>(
+    area: Tile,
+    options: &RenderOptions,
+    tiles: &[Tile],
+    steps: &[usize],
+    evaluate: E,
+) -> Option<(Vec<f64>, RenderStats)>
+where
+    E: Fn(usize, usize, &mut RenderStats) -> f64 + Sync,
+{
+    let n = options.supersample.max(1) as usize;
+    let per_pixel = n * n;
+    let width = area.width as usize;
+    let pixels = width * area.height as usize;
+    let on_grid = |index: usize, step: usize| {
+        (index % width).is_multiple_of(step) && (index / width).is_multiple_of(step)
+    };
+    let reporter = Reporter::new(options.progress.as_ref(), pixels as u64);
+    let color_tiles = steps.len() > 1 && !options.coloring.needs_whole_frame();
+
+    let mut values = vec![0.0; pixels * per_pixel];
+    let mut stats = RenderStats::default();
+    let mut previous: Option<usize> = None;
+    for &step in steps {
+        let pending = |index: usize| {
+            let pending = on_grid(index, step) && !previous.is_some_and(|p| on_grid(index, p));
+            pending.then_some(index)
+        };
+        let earlier = (color_tiles && step == 1).then(|| values.clone());
+        let finish = |tile: &Tile, indices: &[usize], samples: &[f64]| {
+            let image = earlier
+                .as_ref()
+                .map(|earlier| tile_image(options, tile, width, indices, samples, earlier));
+            reporter.finish_tile(*tile, indices.len() as u64, image);
+        };
+        stats = stats
+            + sample_tiles(
+                tiles,
+                width,
+                per_pixel,
+                &options.cancel,
+                &mut values,
+                pending,
+                &finish,
+                &evaluate,
+            )?;
+
+        if step > 1 {
+            let mut positions: Vec<f64> = (0..pixels)
+                .filter(|&index| on_grid(index, step))
+                .flat_map(|index| values[index * per_pixel..(index + 1) * per_pixel].to_vec())
+                .collect();
+            options.coloring.to_positions(&mut positions);
+            let columns = width.div_ceil(step);
+            let image = ImageBuffer::from_fn(area.width, area.height, |x, y| {
+                let slot = y as usize / step * columns + x as usize / step;
+                encode(average_color(
+                    options,
+                    &positions[slot * per_pixel..(slot + 1) * per_pixel],
+                ))
+            });
+            reporter.preview(step as u32, image);
+        }
+        previous = Some(step);
+    }
+    Some((values, stats))
+}
+
+/// Final colors of `tile`, whose pixels `indices` have just been computed
+/// with `samples` and whose others are in `earlier`. Only valid for colorings
+/// that do not need the whole frame.
+fn tile_image(
+    options: &RenderOptions,
+    tile: &Tile,
+    width: usize,
+    indices: &[usize],
+    samples: &[f64],
+    earlier: &[f64],
+) -> RgbImage {
+    let per_pixel = (options.supersample.max(1) * options.supersample.max(1)) as usize;
+    let mut fresh = indices.iter().zip(samples.chunks(per_pixel)).peekable();
+    let mut positions = Vec::with_capacity((tile.width * tile.height) as usize * per_pixel);
+    for index in tile.pixels(width as u32) {
+        match fresh.next_if(|&(&fresh_index, _)| fresh_index == index) {
+            Some((_, samples)) => positions.extend_from_slice(samples),
+            None => {
+                positions.extend_from_slice(&earlier[index * per_pixel..(index + 1) * per_pixel])
+            }
+        }
+    }
+    options.coloring.to_positions(&mut positions);
+    ImageBuffer::from_fn(tile.width, tile.height, |x, y| {
+        let slot = (y * tile.width + x) as usize;
+        encode(average_color(
+            options,
+            &positions[slot * per_pixel..(slot + 1) * per_pixel],
+        ))
+    })
+}
+
+/// Grid-sampled first-pass values laid out pixel by pixel, with each tile's
+/// part of the sample lattice evaluated by rectangle subdivision.
+fn subdivide<E>(
+    area: Tile,
+    options: &RenderOptions,
+    tiles: &[Tile],
+    evaluate: E,
+) -> Option<(Vec<f64>, RenderStats)>
+where
+    E: Fn(f64, f64, &mut RenderStats) -> f64 + Sync,
+{
+    let n = options.supersample.max(1) as usize;
+    let per_pixel = n * n;
+    let width = area.width as usize;
+    let pixels = width * area.height as usize;
+    let guarded = options.subdivision == Subdivision::Guarded;
+    let reporter = Reporter::new(options.progress.as_ref(), pixels as u64);
+    let lattice = |u: usize, v: usize, stats: &mut RenderStats| {
+        let x = area.x as f64 + (u as f64 + 0.5) / n as f64;
+        let y = area.y as f64 + (v as f64 + 0.5) / n as f64;
+        evaluate(x, y, stats)
+    };
+    let results = tiles::run(tiles, &options.cancel, |tile| {
+        let sampled = subdivision::sample(
+            tile.x as usize * n,
+            tile.y as usize * n,
+            tile.width as usize * n,
+            tile.height as usize * n,
+            guarded,
+            &lattice,
+        );
+        reporter.finish_tile(*tile, (tile.width * tile.height) as u64, None);
+        sampled
+    })?;
+
+    let mut values = vec![0.0; pixels * per_pixel];
+    let mut stats = RenderStats::default();
+    for (tile, (samples, tile_stats)) in tiles.iter().zip(results) {
+        let lattice_width = tile.width as usize * n;
+        for (k, &value) in samples.iter().enumerate() {
+            let u = tile.x as usize * n + k % lattice_width;
+            let v = tile.y as usize * n + k / lattice_width;
+            let pixel = v / n * width + u / n;
+            values[pixel * per_pixel + v % n * n + u % n] = value;
+        }
+        stats = stats + tile_stats;
+    }
+    Some((values, stats))
+}
+
+/// Colors the samples in `raw` with the coloring and palette of `options`,
+/// as a render with those options would have.
+pub fn recolor(raw: &RawData, options: &RenderOptions) -> RgbImage {
+    let per_pixel = (raw.supersample.max(1) as usize).pow(2);
+    let mut values: Vec<f64> = raw
+        .escapes
+        .par_iter()
+        .map(|escape| {
+            options
+                .coloring
+                .value(escape, raw.degree, raw.pixel_size)
+                .unwrap_or(f64::NAN)
+        })
+        .collect();
+    options.coloring.to_positions(&mut values);
+
+    let mut img = ImageBuffer::new(raw.width, raw.height);
+    img.par_enumerate_pixels_mut().for_each(|(x, y, pixel)| {
+        let index = y as usize * raw.width as usize + x as usize;
+        *pixel = encode(average_color(
+            options,
+            &values[index * per_pixel..(index + 1) * per_pixel],
+        ));
+    });
     img
 }
 
-fn recursive_fractal_sequence(
-    re_c: f64,
-    im_c: f64,
-    re_z: f64,
-    im_z: f64,
-    depth: usize,
-    max_iterations: usize,
-) -> usize {
-    if depth >= max_iterations || (re_z * re_z + im_z * im_z) > 4.0 {
-        return depth;
+/// Mean linear color of palette `positions`; NaN marks the interior.
+fn average_color(options: &RenderOptions, positions: &[f64]) -> [f64; 3] {
+    let mut sum = [0.0; 3];
+    for &position in positions {
+        let color = match position.is_nan() {
+            true => INTERIOR,
+            false => options.palette.color(position),
+        };
+        for (total, channel) in sum.iter_mut().zip(color) {
+            *total += channel;
+        }
+    }
+    sum.map(|total| total / positions.len() as f64)
+}
+
+fn encode(linear: [f64; 3]) -> Rgb<u8> {
+    Rgb(linear.map(|channel| (palette::linear_to_srgb(channel) * 255.0) as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formula::{BurningShip, Quadratic};
+    use crate::trap::TrapInput;
+
+    const DEEP_ITERATIONS: u64 = 10_000_000;
+
+    fn kernel(max_iterations: u64, bailout: f64) -> Kernel {
+        Kernel {
+            max_iterations,
+            bailout,
+            derivative: Derivative::None,
+            detect_periods: false,
+        }
+    }
+
+    #[test]
+    fn interior_point_survives_deep_iteration() {
+        let c = Complex::new(-0.1, 0.1);
+        let escape = kernel(DEEP_ITERATIONS, 2.0).escape(&Quadratic, c, Complex::ZERO);
+        assert!(!escape.escaped());
+        assert_eq!(escape.iterations, DEEP_ITERATIONS);
+    }
+
+    #[test]
+    fn escaping_point_stops_early() {
+        let c = Complex::new(1.0, 1.0);
+        let escape = kernel(DEEP_ITERATIONS, 2.0).escape(&Quadratic, c, Complex::ZERO);
+        assert!(escape.escaped());
+        assert_eq!(escape.iterations, 2);
+    }
+
+    #[test]
+    fn slow_f32_escape_is_not_periodic() {
+        // Just outside the cusp, the orbit crawls through a narrow channel
+        // for hundreds of steps before escaping.
+        let kernel = Kernel {
+            detect_periods: true,
+            ..kernel(10_000, 2.0)
+        };
+        let c = Complex::<f32>::new(0.2501, 0.0);
+        let escape = kernel.escape(&Quadratic, c, Complex::ZERO);
+        assert_eq!(escape.exit, Exit::Escaped);
+        assert!(escape.iterations > 100);
+    }
+
+    #[test]
+    fn smooth_count_does_not_depend_on_bailout() {
+        let c = Complex::new(-0.75, 0.1);
+        let small = kernel(1000, 1.0e2).escape(&Quadratic, c, Complex::ZERO);
+        let large = kernel(1000, 1.0e4).escape(&Quadratic, c, Complex::ZERO);
+        assert!(large.iterations > small.iterations);
+        assert!((small.smooth(2.0) - large.smooth(2.0)).abs() < 1.0e-2);
+    }
+
+    #[test]
+    fn deep_render_does_not_overflow_worker_stacks() {
+        let viewport = Viewport::new(Complex::new(-0.1, 0.1), 1.0e-3, 2, 2);
+        let options = RenderOptions {
+            interior_checks: false,
+            ..RenderOptions::new(DEEP_ITERATIONS)
+        };
+        let (img, _) = mandelbrot(&Quadratic, &viewport, &options);
+        assert!(img.pixels().all(|pixel| pixel.0 == [0, 0, 0]));
+
+        let (img, _) = julia(&Quadratic, &viewport, &options, Complex::ZERO);
+        assert!(img.pixels().all(|pixel| pixel.0 == [0, 0, 0]));
+    }
+
+    #[test]
+    fn double_double_resolves_views_past_f64() {
+        use crate::double_double::DoubleDouble;
+
+        // Two points 1e-20 apart near the boundary: the same in f64, separate
+        // orbits in double-double.
+        let c = Complex::new(-0.75, 0.1);
+        let shifted = Complex::new(
+            DoubleDouble::parse_decimal("-0.75").unwrap(),
+            DoubleDouble::parse_decimal("0.10000000000000000001").unwrap(),
+        );
+        let kernel = kernel(1000, 2.0);
+        let single = kernel.escape(&Quadratic, c, Complex::ZERO);
+        let double = kernel.escape(
+            &Quadratic,
+            Complex::<DoubleDouble>::from_f64(c),
+            Complex::ZERO,
+        );
+        let moved = kernel.escape(&Quadratic, shifted, Complex::ZERO);
+        assert_eq!(single.iterations, double.iterations);
+        assert!((single.z - double.z).norm() < 1.0e-6);
+        assert_ne!(double.z, moved.z);
+    }
+
+    fn render_sampled(sampling: Sampling) -> (RgbImage, RenderStats) {
+        let viewport = Viewport::new(Complex::new(-0.75, 0.1), 0.05, 96, 54);
+        let options = RenderOptions {
+            supersample: 3,
+            sampling,
+            ..RenderOptions::new(500)
+        };
+        mandelbrot(&Quadratic, &viewport, &options)
+    }
+
+    #[test]
+    fn adaptive_sampling_matches_full_supersampling() {
+        let (full, _) = render_sampled(Sampling::RotatedGrid);
+        let (adaptive, stats) = render_sampled(Sampling::Adaptive { threshold: 0.02 });
+
+        assert!(stats.refined > 0 && stats.refined < full.len() as u64 / 3);
+        let differing = full
+            .pixels()
+            .zip(adaptive.pixels())
+            .filter(|(a, b)| a.0.iter().zip(b.0).any(|(&x, y)| x.abs_diff(y) > 8))
+            .count();
+        assert!(
+            differing * 50 < full.len() / 3,
+            "{} pixels differ",
+            differing
+        );
     }
 
-    let new_re_z: f64 = re_z * re_z - im_z * im_z + re_c;
-    let new_im_z: f64 = (2.0 * im_z * re_z) + im_c;
+    #[test]
+    fn jittered_sampling_is_repeatable() {
+        let (first, _) = render_sampled(Sampling::Jittered { seed: 7 });
+        let (again, _) = render_sampled(Sampling::Jittered { seed: 7 });
+        let (other, _) = render_sampled(Sampling::Jittered { seed: 8 });
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+    }
+
+    fn standard_views() -> Vec<Viewport> {
+        vec![
+            Viewport::new(Complex::new(-0.5, 0.0), 2.0, 96, 54),
+            Viewport::new(Complex::new(-0.75, 0.1), 0.05, 96, 54),
+            Viewport::new(Complex::new(-1.25, 0.0), 0.2, 96, 54),
+        ]
+    }
+
+    #[test]
+    fn subdivision_matches_brute_force() {
+        let mut filled = 0;
+        for viewport in standard_views() {
+            for coloring in [Coloring::Smooth, Coloring::Iterations] {
+                for supersample in [1, 2] {
+                    let brute = RenderOptions {
+                        coloring,
+                        supersample,
+                        ..RenderOptions::new(1000)
+                    };
+                    let (expected, _) = mandelbrot(&Quadratic, &viewport, &brute);
+                    for subdivision in [Subdivision::On, Subdivision::Guarded] {
+                        let options = RenderOptions {
+                            subdivision,
+                            ..brute.clone()
+                        };
+                        let (img, stats) = mandelbrot(&Quadratic, &viewport, &options);
+                        assert_eq!(img, expected);
+                        filled += stats.filled;
+                    }
+                }
+            }
+        }
+        assert!(filled > 0);
+    }
+
+    #[test]
+    fn progressive_render_matches_direct_render() {
+        let viewport = standard_views()[1];
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let options = RenderOptions {
+            supersample: 2,
+            progress: Some(sender),
+            tile_size: 16,
+            ..RenderOptions::new(1000)
+        };
+        let (progressive, _) = mandelbrot(&Quadratic, &viewport, &options);
+        drop(options);
+        let (direct, _) = mandelbrot(&Quadratic, &viewport, &RenderOptions::new(1000));
+        let (supersampled, _) = mandelbrot(
+            &Quadratic,
+            &viewport,
+            &RenderOptions {
+                supersample: 2,
+                ..RenderOptions::new(1000)
+            },
+        );
+        assert_eq!(progressive, supersampled);
+        assert_ne!(progressive, direct);
+
+        let events: Vec<Progress> = receiver.iter().collect();
+        let steps: Vec<u32> = events
+            .iter()
+            .filter_map(|event| match event {
+                Progress::Preview { step, .. } => Some(*step),
+                Progress::Work { .. } | Progress::Tile { .. } => None,
+            })
+            .collect();
+        assert_eq!(steps, [4, 2]);
+
+        let mut colored = 0;
+        for event in &events {
+            if let Progress::Tile {
+                tile,
+                image: Some(image),
+            } = event
+            {
+                let expected = image::imageops::crop_imm(
+                    &progressive,
+                    tile.x,
+                    tile.y,
+                    tile.width,
+                    tile.height,
+                );
+                assert_eq!(*image, expected.to_image());
+                colored += 1;
+            }
+        }
+        assert_eq!(
+            colored,
+            tiles::layout(96, 54, 16, TileOrder::CenterOut).len()
+        );
+        let pixels = (viewport.width * viewport.height) as u64;
+        assert!(matches!(
+            events.last(),
+            Some(Progress::Work { done, total }) if *done == pixels && *total == pixels
+        ));
+    }
 
-    recursive_fractal_sequence(re_c, im_c, new_re_z, new_im_z, depth + 1, max_iterations)
+    #[test]
+    fn tiling_does_not_change_image() {
+        let viewport = standard_views()[2];
+        let (expected, _) = mandelbrot(&Quadratic, &viewport, &RenderOptions::new(1000));
+        for (tile_size, tile_order) in [
+            (1, TileOrder::Rows),
+            (7, TileOrder::Spiral),
+            (1000, TileOrder::CenterOut),
+        ] {
+            let options = RenderOptions {
+                tile_size,
+                tile_order,
+                ..RenderOptions::new(1000)
+            };
+            assert_eq!(mandelbrot(&Quadratic, &viewport, &options).0, expected);
+        }
+    }
+
+    #[test]
+    fn cancelled_render_reports_cancellation() {
+        let options = RenderOptions::new(1000);
+        options.cancel.cancel();
+        let (img, stats) = mandelbrot(&Quadratic, &standard_views()[0], &options);
+        assert!(stats.cancelled);
+        assert_eq!(stats.samples, 0);
+        assert!(img.pixels().all(|pixel| pixel.0 == [0, 0, 0]));
+    }
+
+    fn assert_interior_checks_preserve_image(
+        render: impl Fn(&RenderOptions) -> (RgbImage, RenderStats),
+    ) -> RenderStats {
+        let fast = RenderOptions::new(2000);
+        let slow = RenderOptions {
+            interior_checks: false,
+            ..fast.clone()
+        };
+
+        let (fast_img, fast_stats) = render(&fast);
+        let (slow_img, slow_stats) = render(&slow);
+        assert_eq!(fast_img, slow_img);
+        assert_eq!(fast_stats.interior, slow_stats.interior);
+        assert_eq!(slow_stats.known_interior + slow_stats.periodic, 0);
+        fast_stats
+    }
+
+    #[test]
+    fn interior_checks_do_not_change_mandelbrot() {
+        let mut shortcuts = RenderStats::default();
+        for viewport in standard_views() {
+            shortcuts = shortcuts
+                + assert_interior_checks_preserve_image(|options| {
+                    mandelbrot(&Quadratic, &viewport, options)
+                });
+            assert_interior_checks_preserve_image(|options| {
+                mandelbrot(&BurningShip, &viewport, options)
+            });
+        }
+        assert!(shortcuts.known_interior > 0);
+        assert!(shortcuts.periodic > 0);
+    }
+
+    #[test]
+    fn orbit_traps_follow_the_orbit() {
+        let mut options = RenderOptions::new(10);
+        options.trap.center = Complex::new(1.0, 0.0);
+        let trapped = |options: &RenderOptions, z: f64| {
+            let mut stats = RenderStats::default();
+            let zero = Complex::ZERO;
+            kernel(10, 2.0).trapped(&Quadratic, zero, Complex::new(z, 0.0), options, &mut stats)
+        };
+        // z -> z^2 from 0.5 visits 0.25, 0.0625, ... moving away from 1.
+        options.coloring = Coloring::OrbitTrap {
+            input: TrapInput::Min,
+        };
+        assert_eq!(trapped(&options, 0.5), 0.75);
+        assert!(trapped(&options, 1.5).is_nan());
+        options.coloring = Coloring::OrbitTrap {
+            input: TrapInput::Iteration,
+        };
+        assert_eq!(trapped(&options, 0.5), 1.0);
+
+        // Interior orbits are iterated in full and colored.
+        options.max_iterations = 200;
+        options.coloring = Coloring::OrbitTrap {
+            input: TrapInput::Average,
+        };
+        let viewport = Viewport::new(Complex::new(-0.5, 0.0), 3.0, 40, 30);
+        let (img, stats) = mandelbrot(&Quadratic, &viewport, &options);
+        assert!(stats.interior > 0);
+        assert_eq!(stats.known_interior + stats.periodic, 0);
+        assert_ne!(img.get_pixel(20, 15).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn averages_are_continuous_across_iteration_bands() {
+        let kernel = kernel(1000, 1000.0);
+        for average in [
+            Average::Stripe { density: 5.0 },
+            Average::TriangleInequality,
+            Average::Curvature,
+        ] {
+            let mut crossings = 0;
+            let mut previous: Option<(u64, f64)> = None;
+            for step in 0..20_000 {
+                let c = Complex::new(0.4 + step as f64 * 1.0e-5, 0.3);
+                let iterations = kernel.escape(&Quadratic, c, Complex::ZERO).iterations;
+                let mut stats = RenderStats::default();
+                let value = kernel.averaged(&Quadratic, c, Complex::ZERO, average, &mut stats);
+                assert!((0.0..=1.0).contains(&value), "{:?} {}", average, value);
+                if let Some((last_iterations, last_value)) = previous
+                    && last_iterations != iterations
+                {
+                    crossings += 1;
+                    assert!(
+                        (value - last_value).abs() < 0.01,
+                        "{:?} at {:?}",
+                        average,
+                        c
+                    );
+                }
+                previous = Some((iterations, value));
+            }
+            assert!(crossings > 5);
+
+            // Escaping on the first step leaves curvature with no term at all.
+            let mut stats = RenderStats::default();
+            let c = Complex::new(3.0, 0.0);
+            let value = kernel.averaged(&Quadratic, c, Complex::ZERO, average, &mut stats);
+            assert!((0.0..=1.0).contains(&value), "{:?} {}", average, value);
+        }
+    }
+
+    #[test]
+    fn interior_checks_do_not_change_julia() {
+        let viewport: Viewport = Viewport::new(Complex::ZERO, 3.0, 96, 54);
+        let mut periodic = 0;
+        for c in [
+            Complex::new(-0.8, 0.156),
+            Complex::new(-0.12, 0.75),
+            Complex::new(-1.0, 0.0),
+        ] {
+            let stats = assert_interior_checks_preserve_image(|options| {
+                julia(&Quadratic, &viewport, options, c)
+            });
+            periodic += stats.periodic;
+        }
+        assert!(periodic > 0);
+    }
 }
@@ -0,0 +1,147 @@
+use image::RgbImage;
+
+use crate::coloring::Coloring;
+use crate::fractal::{self, RenderOptions, RenderStats};
+use crate::palette::Palette;
+use crate::viewport::Viewport;
+
+/// A Markus–Lyapunov fractal: the Lyapunov exponent of the logistic map
+/// `x -> r x (1 - x)`, with `r` switching between the two axes of the image,
+/// `a` and `b`, in the order given by a sequence such as `AABAB`.
+#[derive(Debug, Clone)]
+pub struct Lyapunov {
+    /// The sequence, `false` for `A` and `true` for `B`.
+    pub sequence: Vec<bool>,
+    /// Range of `a` across the image, left to right.
+    pub a: (f64, f64),
+    /// Range of `b` across the image, bottom to top.
+    pub b: (f64, f64),
+    /// Steps taken before the exponent starts being measured, so that `x`
+    /// has settled onto its attractor.
+    pub warmup: u64,
+    /// A two-sided palette, for `Coloring::Lyapunov`.
+    pub palette: Palette,
+}
+
+impl Lyapunov {
+    /// Reads a sequence of `A`s and `B`s, in either case.
+    pub fn parse_sequence(text: &str) -> Option<Vec<bool>> {
+        let sequence: Option<Vec<bool>> = text
+            .chars()
+            .map(|letter| match letter.to_ascii_uppercase() {
+                'A' => Some(false),
+                'B' => Some(true),
+                _ => None,
+            })
+            .collect();
+        sequence.filter(|sequence| !sequence.is_empty())
+    }
+
+    /// The default palette halves: stable regions from dark brown to gold as
+    /// the exponent rises to zero, chaotic regions from black to blue.
+    pub fn default_palettes() -> (Palette, Palette) {
+        let stable = [
+            (0.0, [20, 10, 0]),
+            (0.7, [200, 120, 20]),
+            (1.0, [255, 230, 90]),
+        ];
+        let chaotic = [(0.0, [0, 0, 0]), (1.0, [70, 140, 255])];
+        (
+            Palette::from_srgb_stops(&stable),
+            Palette::from_srgb_stops(&chaotic),
+        )
+    }
+
+    /// The exponent at `(a, b)`, averaged over `iterations` steps after the
+    /// warm-up. Orbits that leave `[0, 1]` give NaN.
+    ///
+    /// `x` starts at 0.4 rather than the customary 0.5: the derivative of
+    /// the map is zero at 0.5, which would make the first term `-inf` without
+    /// a warm-up, and `r = 4` maps 0.5 straight onto the fixed point 0.
+    pub fn exponent(&self, a: f64, b: f64, iterations: u64) -> f64 {
+        let rate = |step: u64| match self.sequence[(step % self.sequence.len() as u64) as usize] {
+            false => a,
+            true => b,
+        };
+        let mut x = 0.4;
+        for step in 0..self.warmup {
+            x = rate(step) * x * (1.0 - x);
+        }
+        let mut sum = 0.0;
+        for step in self.warmup..self.warmup + iterations {
+            let r = rate(step);
+            sum += (r * (1.0 - 2.0 * x)).abs().ln();
+            x = r * x * (1.0 - x);
+        }
+        if !(0.0..=1.0).contains(&x) {
+            return f64::NAN;
+        }
+        sum / iterations.max(1) as f64
+    }
+
+    /// Renders the `a`/`b` ranges stretched over `viewport`, whose extent
+    /// only sets the frame the ranges map onto, through the same sampling,
+    /// tiling and output as escape-time renders. `options.max_iterations`
+    /// is the number of steps measured.
+    pub fn render(&self, viewport: &Viewport, options: &RenderOptions) -> (RgbImage, RenderStats) {
+        let options = RenderOptions {
+            coloring: Coloring::Lyapunov,
+            palette: self.palette.clone(),
+            ..options.clone()
+        };
+        let [width, height] = viewport.extent();
+        let a_center = (self.a.0 + self.a.1) / 2.0;
+        let b_center = (self.b.0 + self.b.1) / 2.0;
+        fractal::render_values(viewport, &options, |offset, stats| {
+            stats.samples += 1;
+            let a = a_center + offset.re / width * (self.a.1 - self.a.0);
+            let b = b_center + offset.im / height * (self.b.1 - self.b.0);
+            self.exponent(a, b, options.max_iterations)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::complex::Complex;
+
+    #[test]
+    fn exponent_sign_matches_logistic_map() {
+        let (stable, chaotic) = Lyapunov::default_palettes();
+        let lyapunov = Lyapunov {
+            sequence: Lyapunov::parse_sequence("ab").unwrap(),
+            a: (2.0, 4.0),
+            b: (2.0, 4.0),
+            warmup: 200,
+            palette: Palette::two_sided(&stable, &chaotic),
+        };
+        // A fixed point at r = 2.5, a 2-cycle at r = 3.2 and chaos at r = 3.9.
+        assert!(lyapunov.exponent(2.5, 2.5, 1000) < 0.0);
+        assert!(lyapunov.exponent(3.2, 3.2, 1000) < 0.0);
+        assert!(lyapunov.exponent(3.9, 3.9, 1000) > 0.3);
+        assert!(lyapunov.exponent(4.0, 4.0, 1000) > 0.6);
+
+        // Without a warm-up the first step is measured too, and is finite.
+        let cold = Lyapunov {
+            warmup: 0,
+            ..lyapunov.clone()
+        };
+        assert!(cold.exponent(2.5, 2.5, 1000).is_finite());
+        assert!(cold.exponent(3.9, 3.9, 1000) > 0.3);
+        assert_eq!(Lyapunov::parse_sequence("AAbX"), None);
+
+        let viewport = Viewport::new(Complex::ZERO, 1.0, 40, 30);
+        let (img, stats) = lyapunov.render(&viewport, &RenderOptions::new(200));
+        assert_eq!(stats.samples, 40 * 30);
+        // Stable in the lower left corner, chaotic in the upper right.
+        let gold = img.get_pixel(0, 29).0;
+        let blue = img.get_pixel(39, 0).0;
+        assert!(
+            gold[0] > gold[2] && blue[2] > blue[0],
+            "{:?} {:?}",
+            gold,
+            blue
+        );
+    }
+}
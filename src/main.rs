@@ -1,26 +1,182 @@
+mod averaging;
+mod buddhabrot;
+mod cli;
+mod coloring;
+mod complex;
+mod double_double;
+mod fixed;
+mod float_exp;
+mod formula;
 mod fractal;
+mod lyapunov;
+mod palette;
+mod perturbation;
+mod progress;
+mod pyramid;
+mod random;
+mod raw;
+mod real;
+mod sampling;
+mod stream;
+mod subdivision;
+mod tiles;
+mod trap;
+mod viewport;
 mod window;
 
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::{Arc, mpsc};
+use std::thread;
 use std::time::Instant;
 
+use averaging::Average;
+use buddhabrot::{DensityOptions, Tone, Variant};
+use cli::Args;
+use coloring::Coloring;
+use complex::Complex;
+use double_double::DoubleDouble;
+use float_exp::FloatExp;
+use formula::{FormulaKind, Quadratic};
+use fractal::{RenderOptions, RenderStats, ViewRender};
+use lyapunov::Lyapunov;
+use palette::{Interpolation, Palette, Wrap};
+use pyramid::{Layout, Pyramid};
+use raw::RawData;
+use real::{Precision, Real};
+use sampling::Sampling;
+use subdivision::Subdivision;
+use tiles::{CancelToken, TileOrder};
+use trap::{Shape, Trap, TrapImage};
+use viewport::{Frame, Viewport};
+
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let cli = Args::parse(std::env::args());
+    let args = cli.positional();
+
+    if args.get(1).is_some_and(|command| command == "recolor") {
+        recolor(&cli);
+        return;
+    }
 
     if args.len() < 4 {
         eprintln!(
-            "\n\nUsage: {} <output_file> <0|1> <max_iterations> [fractal_params...]\n",
+            "\n\nUsage: {} <output_file> <0|1|2|3> <max_iterations> [fractal_params...] [options]\n",
             args[0]
         );
-        eprintln!("fractal_params:  Choice 0 (Mandelbrot): requires <real_center> <imag_center> <zoom_factor>");
-        eprintln!("fractal_params:  Choice 1 (Julia): requires <re_c> <im_c>\n");
+        eprintln!(
+            "fractal_params:  Choice 0 (Mandelbrot): requires <real_center> <imag_center> <zoom_factor>"
+        );
+        eprintln!("fractal_params:  Choice 1 (Julia): requires <re_c> <im_c>");
+        eprintln!(
+            "fractal_params:  Choice 2 (orbit density): requires <real_center> <imag_center> <zoom_factor>"
+        );
+        eprintln!("fractal_params:  Choice 3 (Lyapunov): requires <sequence, e.g. AABAB>\n");
+        eprintln!("options:  --formula <{}>", FormulaKind::NAMES.join("|"));
+        eprintln!("          --power <n>  (multibrot exponent, default 3)");
+        eprintln!(
+            "          --width <px> --height <px>  (default {}x{})",
+            fractal::DEFAULT_WIDTH,
+            fractal::DEFAULT_HEIGHT
+        );
+        eprintln!("          --supersample <n>  (n x n samples per pixel, default 1)");
+        eprintln!(
+            "          --sampling <{}>  (sample placement, default grid)",
+            Sampling::NAMES.join("|")
+        );
+        eprintln!(
+            "          --seed <n>  (jittered sampling seed)  --threshold <t>  (adaptive sampling, default 0.1)"
+        );
+        eprintln!(
+            "          --subdivision <{}>  (fill uniform rectangles without iterating, grid sampling only, default off)",
+            Subdivision::NAMES.join("|")
+        );
+        eprintln!(
+            "          --tile-size <px>  (default {})  --tile-order <{}>  (default center)",
+            fractal::DEFAULT_TILE_SIZE,
+            TileOrder::NAMES.join("|")
+        );
+        eprintln!(
+            "          --progress <on|off>  (progress bar with ETA on stderr, default on in a terminal)"
+        );
+        eprintln!(
+            "          --stream  (write PNG or tiled TIFF strip by strip, for images too big for memory)"
+        );
+        eprintln!("          --resume  (continue an interrupted --stream render)");
+        eprintln!(
+            "          --raw <file.raw>  (also save per-sample iteration data for: {} recolor <file.raw> <output.png> [options])",
+            args[0]
+        );
+        eprintln!(
+            "          --pyramid <{}>  (tile pyramid; output is the .dzi file or the tile directory)",
+            Layout::NAMES.join("|")
+        );
+        eprintln!(
+            "          --pyramid-tile <px>  (default 254 for dzi, 256 for xyz)  --overlap <px>  (dzi, default 1)"
+        );
+        eprintln!(
+            "          --variant <{}>  (orbit density, default buddhabrot)",
+            Variant::NAMES.join("|")
+        );
+        eprintln!(
+            "          --samples <n>  (orbits, default 20 per pixel)  --limits <r,g,b>  (nebulabrot iterations)"
+        );
+        eprintln!("          --importance <on|off>  (sample near the boundary more, default on)");
+        eprintln!(
+            "          --tone <{}>  --gamma <g>  (density tone mapping, default linear, 2)",
+            Tone::NAMES.join("|")
+        );
+        eprintln!(
+            "          --a-range <min,max>  --b-range <min,max>  (Lyapunov axes, default 2,4)  --warmup <n>  (default 200)"
+        );
+        eprintln!(
+            "          --stable-palette <name|file>  --chaotic-palette <name|file>  (Lyapunov palette halves)"
+        );
+        eprintln!("          --center <re,im>  (Julia view center, default 0,0)");
+        eprintln!("          --span <size>  (extent of the shorter side, overrides zoom)");
+        eprintln!("          --rotation <degrees>  (counter-clockwise, default 0)");
+        eprintln!("          --coloring <{}>", Coloring::NAMES.join("|"));
+        eprintln!("          --bailout <radius>  (escape radius, default 2)");
+        eprintln!("          --thickness <px>  (distance coloring boundary width, default 1)");
+        eprintln!("          --stripe-density <s>  (stripes for stripe coloring, default 5)");
+        eprintln!(
+            "          --trap <{}|image.png>  --trap-center <re,im>  --trap-angle <degrees>  --trap-size <s>",
+            Shape::NAMES.join("|")
+        );
+        eprintln!(
+            "          --interior-checks <on|off>  (cardioid/bulb and periodicity shortcuts, default on)"
+        );
+        eprintln!(
+            "          --precision <{}>  (number type for the orbit, default f64)",
+            Precision::NAMES.join("|")
+        );
+        eprintln!(
+            "          --deep  (perturbation rendering for zooms past 1e13, Mandelbrot only)"
+        );
+        eprintln!(
+            "          --series <on|off>  (skip early deep-zoom iterations by series approximation, default on)"
+        );
+        eprintln!(
+            "          --palette <{}|file.map|file.ggr>",
+            Palette::BUILTIN_NAMES.join("|")
+        );
+        eprintln!("          --interpolation <rgb|oklab>  --palette-mode <cyclic|clamped>");
+        eprintln!("          --palette-offset <t>  --palette-density <d>\n");
 
         eprintln!("images/EXAMPLE.png: cargo run --release images/EXAMPLE.png 0 1000 -1.0 0.0 1.0");
 
-        let img = fractal::mandelbrot(1000, -1.0, 0.0, 1.0);
+        let viewport = Viewport::new(
+            Complex::new(-1.0, 0.0),
+            2.0,
+            fractal::DEFAULT_WIDTH,
+            fractal::DEFAULT_HEIGHT,
+        );
+        let (img, _) = fractal::mandelbrot(&Quadratic, &viewport, &RenderOptions::new(1000));
         img.save("images/EXAMPLE.png")
             .expect("Unable to save example fractal");
 
-        pollster::block_on(window::run_window());
+        let palette_name = cli.option("palette").unwrap_or("rainbow").to_string();
+        pollster::block_on(window::run_window(palette_name, palette_from_args(&cli)));
 
         std::process::exit(1);
     }
@@ -28,18 +184,161 @@ fn main() {
     let output_file = &args[1];
     let set_choice: u8 = args[2]
         .parse()
-        .expect("Set choice (arg 2) must be a 0 or 1");
+        .expect("Set choice (arg 2) must be 0, 1, 2 or 3");
 
     if args.len() != 7 && set_choice == 0 {
         eprintln!("Not enough arguements for mandelbrot.");
     }
-    let max_recursion: u32 = args[3]
+    let max_recursion: u64 = args[3]
         .parse()
         .expect("Max iterations (arg 3) must be a positive integer.");
 
-    let img;
-    let time_elapsed;
-    match set_choice {
+    let formula_name = cli.option("formula").unwrap_or("mandelbrot");
+    let power: u32 = cli.parse_option("power", 3);
+    if power < 2 {
+        eprintln!("--power must be at least 2.");
+        std::process::exit(1);
+    }
+    let formula = FormulaKind::from_name(formula_name, power).unwrap_or_else(|| {
+        eprintln!(
+            "Unknown formula '{}'. Expected one of: {}",
+            formula_name,
+            FormulaKind::NAMES.join(", ")
+        );
+        std::process::exit(1);
+    });
+
+    let width = cli.parse_option("width", fractal::DEFAULT_WIDTH);
+    let height = cli.parse_option("height", fractal::DEFAULT_HEIGHT);
+    let sampling_name = cli.option("sampling").unwrap_or("grid");
+    let mut sampling = Sampling::from_name(sampling_name).unwrap_or_else(|| {
+        eprintln!(
+            "Unknown sampling '{}'. Expected one of: {}",
+            sampling_name,
+            Sampling::NAMES.join(", ")
+        );
+        std::process::exit(1);
+    });
+    match &mut sampling {
+        Sampling::Jittered { seed } => *seed = cli.parse_option("seed", *seed),
+        Sampling::Adaptive { threshold } => {
+            *threshold = cli.parse_option("threshold", *threshold);
+        }
+        Sampling::Grid | Sampling::RotatedGrid => {}
+    }
+    let default_supersample = match sampling {
+        Sampling::Adaptive { .. } => 4,
+        _ => 1,
+    };
+    let supersample = cli.parse_option("supersample", default_supersample);
+    if width == 0 || height == 0 || supersample == 0 {
+        eprintln!("--width, --height and --supersample must be positive.");
+        std::process::exit(1);
+    }
+
+    let subdivision_name = cli.option("subdivision").unwrap_or("off");
+    let subdivision = Subdivision::from_name(subdivision_name).unwrap_or_else(|| {
+        eprintln!(
+            "Unknown subdivision '{}'. Expected one of: {}",
+            subdivision_name,
+            Subdivision::NAMES.join(", ")
+        );
+        std::process::exit(1);
+    });
+    if subdivision != Subdivision::Off && sampling != Sampling::Grid {
+        eprintln!("--subdivision requires grid sampling.");
+        std::process::exit(1);
+    }
+
+    let tile_size = cli.parse_option("tile-size", fractal::DEFAULT_TILE_SIZE);
+    if tile_size == 0 {
+        eprintln!("--tile-size must be positive.");
+        std::process::exit(1);
+    }
+    let tile_order_name = cli.option("tile-order").unwrap_or("center");
+    let tile_order = TileOrder::from_name(tile_order_name).unwrap_or_else(|| {
+        eprintln!(
+            "Unknown tile order '{}'. Expected one of: {}",
+            tile_order_name,
+            TileOrder::NAMES.join(", ")
+        );
+        std::process::exit(1);
+    });
+
+    let coloring = coloring_from_args(&cli);
+    let bailout = cli
+        .option("bailout")
+        .map(|_| cli.parse_option("bailout", 2.0_f64));
+    if bailout.is_some_and(|radius| radius <= 1.0) {
+        eprintln!("--bailout must be greater than 1.");
+        std::process::exit(1);
+    }
+
+    let (progress, receiver) = mpsc::channel();
+    let progress_bar = cli
+        .flag("progress", std::io::stderr().is_terminal())
+        .then(|| thread::spawn(|| progress::show_bar(receiver)));
+    let raw_path = cli.option("raw");
+    let (raw, raw_receiver) = mpsc::channel();
+    let options = RenderOptions {
+        max_iterations: max_recursion,
+        supersample,
+        sampling,
+        bailout,
+        coloring,
+        palette: palette_from_args(&cli),
+        interior_checks: cli.flag("interior-checks", true),
+        series_approximation: cli.flag("series", true),
+        subdivision,
+        progress: progress_bar.is_some().then_some(progress),
+        tile_size,
+        tile_order,
+        cancel: CancelToken::new(),
+        crop: None,
+        raw: raw_path.map(|_| raw),
+        trap: trap_from_args(&cli),
+    };
+    let rotation = cli.parse_option("rotation", 0.0_f64).to_radians();
+
+    let streamed = cli.flag("stream", false) || cli.flag("resume", false);
+    let pyramid = cli.option("pyramid").map(|name| {
+        let layout = Layout::from_name(name).unwrap_or_else(|| {
+            eprintln!(
+                "Unknown pyramid layout '{}'. Expected one of: {}",
+                name,
+                Layout::NAMES.join(", ")
+            );
+            std::process::exit(1);
+        });
+        Pyramid {
+            layout,
+            tile_size: cli.parse_option("pyramid-tile", layout.default_tile_size()),
+            overlap: cli.parse_option("overlap", 1),
+        }
+    });
+    if pyramid.is_some_and(|pyramid| pyramid.tile_size == 0) {
+        eprintln!("--pyramid-tile must be positive.");
+        std::process::exit(1);
+    }
+    if pyramid.is_some() && streamed {
+        eprintln!("--pyramid cannot be combined with --stream or --resume.");
+        std::process::exit(1);
+    }
+    let along_orbit = options.coloring.needs_orbit();
+    if raw_path.is_some() && along_orbit {
+        eprintln!("--raw cannot be used with orbit trap or averaging coloring.");
+        std::process::exit(1);
+    }
+    if raw_path.is_some() && (streamed || pyramid.is_some()) {
+        eprintln!("--raw cannot be combined with --stream, --resume or --pyramid.");
+        std::process::exit(1);
+    }
+    if (streamed || pyramid.is_some()) && options.coloring.needs_whole_frame() {
+        eprintln!("--stream, --resume and --pyramid cannot be used with histogram coloring.");
+        std::process::exit(1);
+    }
+
+    let render: Box<ViewRender> = match set_choice {
         0 => {
             if args.len() != 7 {
                 eprintln!("Incorrect number of arguments for Mandelbrot (choice 0).");
@@ -59,9 +358,68 @@ fn main() {
                 .parse()
                 .expect("Zoom factor (arg 6) must be a floating-point number");
 
-            let start_time = Instant::now();
-            img = fractal::mandelbrot(max_recursion, real_center, imaginary_center, zoom_factor);
-            time_elapsed = start_time.elapsed();
+            let mut viewport = Viewport::new(
+                Complex::new(real_center, imaginary_center),
+                cli.parse_option("span", 2.0 / zoom_factor),
+                width,
+                height,
+            );
+            viewport.rotation = rotation;
+
+            if cli.flag("deep", false) {
+                if !matches!(formula, FormulaKind::Quadratic(_)) {
+                    eprintln!("--deep only supports the mandelbrot formula.");
+                    std::process::exit(1);
+                }
+                if along_orbit {
+                    eprintln!("--deep cannot be used with orbit trap or averaging coloring.");
+                    std::process::exit(1);
+                }
+                // Parsed again so spans below the range of f64 survive.
+                let span = match cli.option("span") {
+                    Some(span) => FloatExp::parse_decimal(span),
+                    None => FloatExp::parse_decimal(&args[6])
+                        .map(|zoom| FloatExp::from_f64(2.0) * zoom.recip()),
+                }
+                .expect("Deep zoom spans must be decimal numbers");
+                if !(span > FloatExp::ZERO && span.log2().is_finite()) {
+                    eprintln!("Deep zoom spans must be finite and positive.");
+                    std::process::exit(1);
+                }
+                if span.log2() < -perturbation::MAX_DEPTH_BITS {
+                    eprintln!(
+                        "--deep supports spans down to 2^-{}.",
+                        perturbation::MAX_DEPTH_BITS
+                    );
+                    std::process::exit(1);
+                }
+                let (center, mut viewport) =
+                    perturbation::DeepCenter::parse(&args[4], &args[5], span, width, height)
+                        .expect("Deep zoom centers must be decimal numbers");
+                viewport.rotation = rotation;
+                Box::new(move |frame, options| {
+                    perturbation::mandelbrot(&center, &viewport.framed(frame), options)
+                })
+            } else {
+                let (re, im) = (args[4].as_str(), args[5].as_str());
+                match precision_from_args(&cli, &viewport) {
+                    Precision::F32 => {
+                        let viewport = at_precision::<f32>(&viewport, re, im);
+                        Box::new(move |frame, options| {
+                            fractal::mandelbrot(&formula, &viewport.framed(frame), options)
+                        })
+                    }
+                    Precision::F64 => Box::new(move |frame, options| {
+                        fractal::mandelbrot(&formula, &viewport.framed(frame), options)
+                    }),
+                    Precision::DoubleDouble => {
+                        let viewport = at_precision::<DoubleDouble>(&viewport, re, im);
+                        Box::new(move |frame, options| {
+                            fractal::mandelbrot(&formula, &viewport.framed(frame), options)
+                        })
+                    }
+                }
+            }
         }
         1 => {
             if args.len() != 6 {
@@ -76,13 +434,381 @@ fn main() {
                 .parse()
                 .expect("Imaginary constant C (arg 5) must be a floating-point number");
 
-            let start_time = Instant::now();
-            img = fractal::julia(max_recursion, re_c, im_c);
-            time_elapsed = start_time.elapsed();
+            let mut viewport = Viewport::new(
+                cli.parse_option("center", Complex::ZERO),
+                cli.parse_option("span", 3.0),
+                width,
+                height,
+            );
+            viewport.rotation = rotation;
+
+            let c = Complex::new(re_c, im_c);
+            let (re, im) = cli
+                .option("center")
+                .map(|center| center.split_once(',').unwrap_or((center, "0")))
+                .unwrap_or(("0", "0"));
+
+            match precision_from_args(&cli, &viewport) {
+                Precision::F32 => {
+                    let viewport = at_precision::<f32>(&viewport, re, im);
+                    Box::new(move |frame, options| {
+                        fractal::julia(&formula, &viewport.framed(frame), options, c)
+                    })
+                }
+                Precision::F64 => Box::new(move |frame, options| {
+                    fractal::julia(&formula, &viewport.framed(frame), options, c)
+                }),
+                Precision::DoubleDouble => {
+                    let viewport = at_precision::<DoubleDouble>(&viewport, re, im);
+                    Box::new(move |frame, options| {
+                        fractal::julia(&formula, &viewport.framed(frame), options, c)
+                    })
+                }
+            }
         }
-        _ => panic!("Invalid choice: '{}'. Please enter 0 or 1.", set_choice),
+        2 => {
+            if args.len() != 7 {
+                eprintln!("Incorrect number of arguments for orbit density (choice 2).");
+                eprintln!(
+                    "Usage: {} <output> 2 <max_iter> <real_center> <imag_center> <zoom>",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            if streamed || pyramid.is_some() || raw_path.is_some() {
+                eprintln!("Orbit density renders cannot be streamed, tiled or saved raw.");
+                std::process::exit(1);
+            }
+            let parse = |index: usize, name: &str| -> f64 {
+                args[index].parse().unwrap_or_else(|_| {
+                    eprintln!("{} (arg {}) must be a floating-point number", name, index);
+                    std::process::exit(1);
+                })
+            };
+            let mut viewport = Viewport::new(
+                Complex::new(parse(4, "Real center"), parse(5, "Imaginary center")),
+                cli.parse_option("span", 2.0 / parse(6, "Zoom factor")),
+                width,
+                height,
+            );
+            viewport.rotation = rotation;
+
+            let density = density_from_args(&cli, max_recursion, width, height);
+            Box::new(move |frame, options| {
+                buddhabrot::render(&formula, &viewport.framed(frame), options, &density)
+            })
+        }
+        3 => {
+            if args.len() != 5 {
+                eprintln!("Incorrect number of arguments for Lyapunov (choice 3).");
+                eprintln!("Usage: {} <output> 3 <max_iter> <sequence>", args[0]);
+                std::process::exit(1);
+            }
+            if raw_path.is_some() {
+                eprintln!("Lyapunov renders cannot be saved raw.");
+                std::process::exit(1);
+            }
+            let sequence = Lyapunov::parse_sequence(&args[4]).unwrap_or_else(|| {
+                eprintln!("The Lyapunov sequence (arg 4) must be made of A and B, e.g. AABAB.");
+                std::process::exit(1);
+            });
+            let range = |name: &str| {
+                let text = cli.option(name).unwrap_or("2,4");
+                let parsed = text
+                    .split_once(',')
+                    .and_then(|(low, high)| Some((low.parse().ok()?, high.parse().ok()?)));
+                parsed.unwrap_or_else(|| {
+                    eprintln!("--{} expects two numbers, e.g. 2,4.", name);
+                    std::process::exit(1);
+                })
+            };
+            let sides = |name: &str, default: &Palette| match cli.option(name) {
+                Some(spec) => Palette::from_spec(spec).unwrap_or_else(|err| {
+                    eprintln!("Unable to load palette '{}': {}", spec, err);
+                    std::process::exit(1);
+                }),
+                None => default.clone(),
+            };
+            let (stable, chaotic) = Lyapunov::default_palettes();
+            let lyapunov = Lyapunov {
+                sequence,
+                a: range("a-range"),
+                b: range("b-range"),
+                warmup: cli.parse_option("warmup", 200),
+                palette: Palette::two_sided(
+                    &sides("stable-palette", &stable),
+                    &sides("chaotic-palette", &chaotic),
+                ),
+            };
+            let mut viewport = Viewport::new(Complex::ZERO, 1.0, width, height);
+            viewport.rotation = rotation;
+            Box::new(move |frame, options| lyapunov.render(&viewport.framed(frame), options))
+        }
+        _ => panic!(
+            "Invalid choice: '{}'. Please enter 0, 1, 2 or 3.",
+            set_choice
+        ),
+    };
+
+    let start_time = Instant::now();
+    let written = |result: std::io::Result<RenderStats>| {
+        result.unwrap_or_else(|error| {
+            eprintln!("Unable to write {}: {}", output_file, error);
+            std::process::exit(1);
+        })
+    };
+    let path = Path::new(output_file);
+    let (img, stats) = if let Some(pyramid) = pyramid {
+        let stats = pyramid.export(path, width, height, &options, &render);
+        (None, written(stats))
+    } else if streamed {
+        let resume = cli.flag("resume", false);
+        let stats = stream::render_to_file(path, width, height, &options, resume, &render);
+        (None, written(stats))
+    } else {
+        let (img, stats) = render(Frame::new(width, height), &options);
+        (Some(img), stats)
+    };
+    let time_elapsed = start_time.elapsed();
+
+    // Dropping the options closes the progress channel and ends the bar.
+    drop(options);
+    if let Some(bar) = progress_bar {
+        bar.join().expect("Progress bar thread panicked");
     }
 
-    img.save(output_file).expect("Unable to save image");
+    if let Some(img) = img {
+        img.save(output_file).expect("Unable to save image");
+    }
+    if let (Some(path), Ok(raw)) = (raw_path, raw_receiver.try_recv()) {
+        raw.write(Path::new(path)).unwrap_or_else(|error| {
+            eprintln!("Unable to write {}: {}", path, error);
+            std::process::exit(1);
+        });
+    }
     println!("Time to run fractal: {:.3}s", time_elapsed.as_secs_f64());
+    println!("Render stats: {}", stats);
+}
+
+/// Reads `--precision`, resolving `auto` for `viewport`.
+fn precision_from_args(cli: &Args, viewport: &Viewport) -> Precision {
+    let name = cli.option("precision").unwrap_or("f64");
+    if name == "auto" {
+        let precision = Precision::auto(viewport.pixel_size(), viewport.center.norm())
+            .unwrap_or_else(|| {
+                eprintln!("The view is too deep for double-double precision; try --deep.");
+                Precision::DoubleDouble
+            });
+        println!("Using {} precision.", precision.name());
+        return precision;
+    }
+
+    Precision::from_name(name).unwrap_or_else(|| {
+        eprintln!(
+            "Unknown precision '{}'. Expected one of: {}",
+            name,
+            Precision::NAMES.join(", ")
+        );
+        std::process::exit(1);
+    })
+}
+
+/// `viewport` with its center re-read from the decimal strings `re` and `im`
+/// in precision `T`, so digits beyond `f64` are kept.
+fn at_precision<T: Real>(viewport: &Viewport, re: &str, im: &str) -> Viewport<T> {
+    let parse = |text: &str| {
+        T::parse_decimal(text).unwrap_or_else(|| {
+            eprintln!("Invalid center coordinate '{}'.", text);
+            std::process::exit(1);
+        })
+    };
+    let mut precise = Viewport::new(
+        Complex::new(parse(re), parse(im)),
+        viewport.span,
+        viewport.width,
+        viewport.height,
+    );
+    precise.rotation = viewport.rotation;
+    precise
+}
+
+/// `recolor <input.raw> <output.png> [options]`: colors raw iteration data
+/// saved with `--raw` using the coloring and palette options.
+fn recolor(cli: &Args) {
+    let args = cli.positional();
+    if args.len() != 4 {
+        eprintln!(
+            "Usage: {} recolor <input.raw> <output.png> [--coloring ...] [--palette ...]",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+    let start_time = Instant::now();
+    let raw = RawData::read(Path::new(&args[2])).unwrap_or_else(|error| {
+        eprintln!("Unable to read {}: {}", args[2], error);
+        std::process::exit(1);
+    });
+    let mut options = RenderOptions::new(raw.max_iterations);
+    options.coloring = coloring_from_args(cli);
+    if options.coloring.needs_orbit() {
+        eprintln!(
+            "Orbit trap and averaging colorings need the orbits, which raw files do not keep."
+        );
+        std::process::exit(1);
+    }
+    options.palette = palette_from_args(cli);
+    fractal::recolor(&raw, &options)
+        .save(&args[3])
+        .expect("Unable to save image");
+    println!(
+        "Time to recolor: {:.3}s",
+        start_time.elapsed().as_secs_f64()
+    );
+}
+
+/// Reads the orbit density options. By default Nebulabrot limits its green
+/// and blue channels to a tenth and a hundredth of `max_iterations`, and
+/// twenty orbits are sampled per pixel.
+fn density_from_args(cli: &Args, max_iterations: u64, width: u32, height: u32) -> DensityOptions {
+    let variant_name = cli.option("variant").unwrap_or("buddhabrot");
+    let variant = Variant::from_name(variant_name).unwrap_or_else(|| {
+        eprintln!(
+            "Unknown variant '{}'. Expected one of: {}",
+            variant_name,
+            Variant::NAMES.join(", ")
+        );
+        std::process::exit(1);
+    });
+    let tone_name = cli.option("tone").unwrap_or("linear");
+    let tone = Tone::from_name(tone_name).unwrap_or_else(|| {
+        eprintln!(
+            "Unknown tone mapping '{}'. Expected one of: {}",
+            tone_name,
+            Tone::NAMES.join(", ")
+        );
+        std::process::exit(1);
+    });
+    let limits = match cli.option("limits") {
+        Some(text) => {
+            let limits: Vec<u64> = text
+                .split(',')
+                .filter_map(|limit| limit.parse().ok())
+                .collect();
+            limits.try_into().unwrap_or_else(|_| {
+                eprintln!("--limits expects three iteration counts, e.g. 5000,500,50.");
+                std::process::exit(1);
+            })
+        }
+        None => {
+            [max_iterations, max_iterations / 10, max_iterations / 100].map(|limit| limit.max(1))
+        }
+    };
+    let gamma = cli.parse_option("gamma", 2.0);
+    if gamma <= 0.0 {
+        eprintln!("--gamma must be positive.");
+        std::process::exit(1);
+    }
+
+    DensityOptions {
+        variant,
+        samples: cli.parse_option("samples", width as u64 * height as u64 * 20),
+        seed: cli.parse_option("seed", 0),
+        limits,
+        importance: cli.flag("importance", true),
+        tone,
+        gamma,
+    }
+}
+
+fn coloring_from_args(cli: &Args) -> Coloring {
+    let name = cli.option("coloring").unwrap_or("smooth");
+    let mut coloring = Coloring::from_name(name).unwrap_or_else(|| {
+        eprintln!(
+            "Unknown coloring '{}'. Expected one of: {}",
+            name,
+            Coloring::NAMES.join(", ")
+        );
+        std::process::exit(1);
+    });
+    if let Coloring::Distance { thickness } = &mut coloring {
+        *thickness = cli.parse_option("thickness", *thickness);
+        if *thickness <= 0.0 {
+            eprintln!("--thickness must be positive.");
+            std::process::exit(1);
+        }
+    }
+    if let Coloring::Average {
+        average: Average::Stripe { density },
+    } = &mut coloring
+    {
+        *density = cli.parse_option("stripe-density", *density);
+    }
+    coloring
+}
+
+/// Reads the orbit trap options. `--trap` takes a shape name or the path of
+/// an image to use as the trap.
+fn trap_from_args(cli: &Args) -> Trap {
+    let shape = match cli.option("trap") {
+        None => Shape::Point,
+        Some(name) => Shape::from_name(name).unwrap_or_else(|| {
+            let image = TrapImage::open(Path::new(name)).unwrap_or_else(|err| {
+                eprintln!(
+                    "Unable to load trap image '{}': {}. Built-in traps: {}",
+                    name,
+                    err,
+                    Shape::NAMES.join(", ")
+                );
+                std::process::exit(1);
+            });
+            Shape::Image(Arc::new(image))
+        }),
+    };
+    let angle = cli.parse_option("trap-angle", 0.0_f64).to_radians();
+    let trap = Trap {
+        shape,
+        center: cli.parse_option("trap-center", Complex::ZERO),
+        direction: Complex::new(angle.cos(), angle.sin()),
+        size: cli.parse_option("trap-size", 1.0),
+    };
+    if trap.size <= 0.0 {
+        eprintln!("--trap-size must be positive.");
+        std::process::exit(1);
+    }
+    trap
+}
+
+fn palette_from_args(cli: &Args) -> Palette {
+    let spec = cli.option("palette").unwrap_or("rainbow");
+    let mut palette = Palette::from_spec(spec).unwrap_or_else(|err| {
+        eprintln!("Unable to load palette '{}': {}", spec, err);
+        eprintln!("Built-in palettes: {}", Palette::BUILTIN_NAMES.join(", "));
+        std::process::exit(1);
+    });
+
+    match cli.option("interpolation") {
+        None => {}
+        Some("rgb") => palette.interpolation = Interpolation::LinearRgb,
+        Some("oklab") => palette.interpolation = Interpolation::Oklab,
+        Some(other) => {
+            eprintln!("Unknown interpolation '{}'. Expected rgb or oklab.", other);
+            std::process::exit(1);
+        }
+    }
+    match cli.option("palette-mode") {
+        None => {}
+        Some("cyclic") => palette.wrap = Wrap::Cyclic,
+        Some("clamped") => palette.wrap = Wrap::Clamped,
+        Some(other) => {
+            eprintln!(
+                "Unknown palette mode '{}'. Expected cyclic or clamped.",
+                other
+            );
+            std::process::exit(1);
+        }
+    }
+    palette.offset = cli.parse_option("palette-offset", palette.offset);
+    palette.density = cli.parse_option("palette-density", palette.density);
+
+    palette
 }
@@ -0,0 +1,592 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Color space used to blend between neighbouring stops.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    LinearRgb,
+    Oklab,
+}
+
+impl Interpolation {
+    /// The `--interpolation` value that selects this.
+    pub fn name(self) -> &'static str {
+        match self {
+            Interpolation::LinearRgb => "rgb",
+            Interpolation::Oklab => "oklab",
+        }
+    }
+}
+
+/// What happens to palette positions outside `[0, 1]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wrap {
+    /// Repeat the gradient, blending the last stop back into the first.
+    Cyclic,
+    /// Hold the first/last color.
+    Clamped,
+}
+
+impl Wrap {
+    /// The `--palette-mode` value that selects this.
+    pub fn name(self) -> &'static str {
+        match self {
+            Wrap::Cyclic => "cyclic",
+            Wrap::Clamped => "clamped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Stop {
+    position: f64,
+    linear: [f64; 3],
+    oklab: [f64; 3],
+}
+
+/// A multi-stop color gradient.
+///
+/// Coloring modes produce a palette position; `color` scales it by `density`,
+/// shifts it by `offset` and wraps it according to `wrap` before looking up
+/// the gradient. Colors are returned in linear RGB.
+#[derive(Debug, Clone)]
+pub struct Palette {
+    stops: Vec<Stop>,
+    pub interpolation: Interpolation,
+    pub wrap: Wrap,
+    pub offset: f64,
+    pub density: f64,
+}
+
+impl Palette {
+    pub const BUILTIN_NAMES: &'static [&'static str] =
+        &["rainbow", "ultra", "fire", "ocean", "classic", "grayscale"];
+
+    /// Builds a cyclic palette from `(position, sRGB)` stops sorted by position.
+    pub fn from_srgb_stops(stops: &[(f64, [u8; 3])]) -> Palette {
+        Palette::from_float_stops(
+            stops
+                .iter()
+                .map(|&(position, rgb)| (position, rgb.map(|c| c as f64 / 255.0))),
+        )
+    }
+
+    fn from_float_stops(stops: impl IntoIterator<Item = (f64, [f64; 3])>) -> Palette {
+        let stops = stops
+            .into_iter()
+            .map(|(position, srgb)| {
+                let linear = srgb.map(srgb_to_linear);
+                Stop {
+                    position: position.clamp(0.0, 1.0),
+                    linear,
+                    oklab: linear_to_oklab(linear),
+                }
+            })
+            .collect();
+
+        Palette {
+            stops,
+            interpolation: Interpolation::LinearRgb,
+            wrap: Wrap::Cyclic,
+            offset: 0.0,
+            density: 1.0,
+        }
+    }
+
+    pub fn builtin(name: &str) -> Option<Palette> {
+        let palette = match name {
+            // Approximates the viewer's original HSV hue sweep (saturation
+            // 0.9, value 1): the six primary and secondary hues are exact,
+            // but the blends between them are interpolated, not HSV.
+            "rainbow" => Palette::from_srgb_stops(&[
+                (0.0, [255, 25, 25]),
+                (1.0 / 6.0, [255, 255, 25]),
+                (2.0 / 6.0, [25, 255, 25]),
+                (3.0 / 6.0, [25, 255, 255]),
+                (4.0 / 6.0, [25, 25, 255]),
+                (5.0 / 6.0, [255, 25, 255]),
+            ]),
+            "ultra" => Palette::from_srgb_stops(&[
+                (0.0, [0, 7, 100]),
+                (0.16, [32, 107, 203]),
+                (0.42, [237, 255, 255]),
+                (0.6425, [255, 170, 0]),
+                (0.8575, [0, 2, 0]),
+            ]),
+            "fire" => Palette::from_srgb_stops(&[
+                (0.0, [0, 0, 0]),
+                (0.25, [128, 0, 0]),
+                (0.5, [255, 96, 0]),
+                (0.75, [255, 220, 64]),
+                (0.9, [255, 255, 224]),
+            ]),
+            "ocean" => Palette::from_srgb_stops(&[
+                (0.0, [0, 8, 32]),
+                (0.3, [0, 64, 128]),
+                (0.6, [0, 176, 200]),
+                (0.8, [220, 255, 250]),
+            ]),
+            // The original fixed CPU coloring, black to teal.
+            "classic" => Palette {
+                wrap: Wrap::Clamped,
+                ..Palette::from_srgb_stops(&[(0.0, [0, 0, 0]), (1.0, [0, 255, 153])])
+            },
+            "grayscale" => Palette::from_srgb_stops(&[(0.0, [0, 0, 0]), (0.5, [255, 255, 255])]),
+            _ => return None,
+        };
+        Some(palette)
+    }
+
+    /// Resolves a built-in name, or loads a Fractint `.map` or GIMP `.ggr` file.
+    pub fn from_spec(spec: &str) -> io::Result<Palette> {
+        match Palette::builtin(spec) {
+            Some(palette) => Ok(palette),
+            None => Palette::load(Path::new(spec)),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Palette> {
+        let text = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("map") => Palette::parse_map(&text),
+            Some(ext) if ext.eq_ignore_ascii_case("ggr") => Palette::parse_ggr(&text),
+            _ => Err(invalid_data(format!(
+                "{}: expected a .map or .ggr palette file",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Fractint `.map`: one `R G B` line (0-255) per evenly spaced entry, with
+    /// anything after the third number treated as a comment.
+    fn parse_map(text: &str) -> io::Result<Palette> {
+        let mut colors = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let mut fields = line.split_whitespace();
+            let Some(first) = fields.next() else {
+                continue;
+            };
+            let invalid = || invalid_data(format!("line {}: expected 'R G B' values", number + 1));
+            let mut channels = std::iter::once(first).chain(fields).take(3);
+            let mut rgb = [0u8; 3];
+            for channel in &mut rgb {
+                let field = channels.next().ok_or_else(invalid)?;
+                *channel = field.parse().map_err(|_| invalid())?;
+            }
+            colors.push(rgb);
+        }
+
+        if colors.is_empty() {
+            return Err(invalid_data("map file contains no colors".to_string()));
+        }
+
+        let count = colors.len() as f64;
+        let stops: Vec<_> = colors
+            .into_iter()
+            .enumerate()
+            .map(|(i, rgb)| (i as f64 / count, rgb))
+            .collect();
+        Ok(Palette::from_srgb_stops(&stops))
+    }
+
+    /// GIMP `.ggr`: segments with a midpoint, blend function and RGB or HSV
+    /// color model. Each segment is resampled into plain stops.
+    fn parse_ggr(text: &str) -> io::Result<Palette> {
+        const SAMPLES_PER_SEGMENT: usize = 16;
+
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+        if lines.next() != Some("GIMP Gradient") {
+            return Err(invalid_data("missing 'GIMP Gradient' header".to_string()));
+        }
+
+        let mut line = lines.next();
+        if line.is_some_and(|l| l.starts_with("Name:")) {
+            line = lines.next();
+        }
+        let count: usize = line
+            .and_then(|l| l.parse().ok())
+            .ok_or_else(|| invalid_data("missing segment count".to_string()))?;
+
+        let mut stops = Vec::with_capacity(count * (SAMPLES_PER_SEGMENT + 1));
+        for index in 0..count {
+            let fields: Vec<f64> = lines
+                .next()
+                .ok_or_else(|| invalid_data(format!("missing segment {}", index)))?
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid_data(format!("segment {}: invalid number", index)))?;
+            if fields.len() < 13 {
+                return Err(invalid_data(format!("segment {}: too few fields", index)));
+            }
+
+            let segment = GgrSegment {
+                left: fields[0],
+                middle: fields[1],
+                right: fields[2],
+                left_color: [fields[3], fields[4], fields[5]],
+                right_color: [fields[7], fields[8], fields[9]],
+                blend: fields[11] as u32,
+                model: fields[12] as u32,
+            };
+            for sample in 0..=SAMPLES_PER_SEGMENT {
+                let t = sample as f64 / SAMPLES_PER_SEGMENT as f64;
+                let position = segment.left + (segment.right - segment.left) * t;
+                stops.push((position, segment.color_at(position)));
+            }
+        }
+
+        if stops.is_empty() {
+            return Err(invalid_data("gradient has no segments".to_string()));
+        }
+        Ok(Palette {
+            wrap: Wrap::Clamped,
+            ..Palette::from_float_stops(stops)
+        })
+    }
+
+    /// A clamped palette with the gradient of `negative` on its first half and
+    /// that of `positive` on its second, for colorings that give each sign of
+    /// a value its own colors.
+    pub fn two_sided(negative: &Palette, positive: &Palette) -> Palette {
+        let half = |palette: &Palette, start: f64| {
+            let stops = palette.stops.iter();
+            stops
+                .map(|stop| Stop {
+                    position: start + stop.position / 2.0,
+                    ..*stop
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut stops = half(negative, 0.0);
+        stops.extend(half(positive, 0.5));
+        Palette {
+            stops,
+            interpolation: negative.interpolation,
+            wrap: Wrap::Clamped,
+            offset: 0.0,
+            density: 1.0,
+        }
+    }
+
+    /// Linear RGB color for a palette position produced by a coloring mode.
+    pub fn color(&self, value: f64) -> [f64; 3] {
+        let t = self.offset + self.density * value;
+        let t = match self.wrap {
+            Wrap::Cyclic => t.rem_euclid(1.0),
+            Wrap::Clamped => t.clamp(0.0, 1.0),
+        };
+        self.gradient(t)
+    }
+
+    /// Samples the gradient itself at `size + 1` evenly spaced positions in
+    /// `[0, 1]`, as RGBA, for the viewer's lookup buffer.
+    pub fn lookup_table(&self, size: usize) -> Vec<[f32; 4]> {
+        (0..=size)
+            .map(|i| {
+                let [r, g, b] = self.gradient(i as f64 / size as f64);
+                [r as f32, g as f32, b as f32, 1.0]
+            })
+            .collect()
+    }
+
+    fn gradient(&self, t: f64) -> [f64; 3] {
+        let first = &self.stops[0];
+        let last = &self.stops[self.stops.len() - 1];
+
+        let (from, to, from_position, to_position) = if t < first.position {
+            match self.wrap {
+                Wrap::Cyclic => (last, first, last.position - 1.0, first.position),
+                Wrap::Clamped => return first.linear,
+            }
+        } else if t >= last.position {
+            match self.wrap {
+                Wrap::Cyclic => (last, first, last.position, first.position + 1.0),
+                Wrap::Clamped => return last.linear,
+            }
+        } else {
+            let next = self.stops.partition_point(|stop| stop.position <= t);
+            let (from, to) = (&self.stops[next - 1], &self.stops[next]);
+            (from, to, from.position, to.position)
+        };
+
+        let width = to_position - from_position;
+        let fraction = if width > 0.0 {
+            (t - from_position) / width
+        } else {
+            0.0
+        };
+
+        match self.interpolation {
+            Interpolation::LinearRgb => lerp(from.linear, to.linear, fraction),
+            Interpolation::Oklab => oklab_to_linear(lerp(from.oklab, to.oklab, fraction)),
+        }
+    }
+}
+
+struct GgrSegment {
+    left: f64,
+    middle: f64,
+    right: f64,
+    left_color: [f64; 3],
+    right_color: [f64; 3],
+    blend: u32,
+    model: u32,
+}
+
+impl GgrSegment {
+    fn color_at(&self, position: f64) -> [f64; 3] {
+        let length = self.right - self.left;
+        let (middle, t) = if length > 0.0 {
+            (
+                (self.middle - self.left) / length,
+                (position - self.left) / length,
+            )
+        } else {
+            (0.5, 0.5)
+        };
+
+        let linear = if t <= middle {
+            if middle > 0.0 { 0.5 * t / middle } else { 0.0 }
+        } else if middle < 1.0 {
+            0.5 + 0.5 * (t - middle) / (1.0 - middle)
+        } else {
+            1.0
+        };
+        let factor = match self.blend {
+            // Curved, sine, sphere increasing and sphere decreasing.
+            1 => t.powf(0.5f64.ln() / middle.max(1.0e-10).ln()),
+            2 => ((std::f64::consts::PI * (linear - 0.5)).sin() + 1.0) / 2.0,
+            3 => (1.0 - (linear - 1.0) * (linear - 1.0)).max(0.0).sqrt(),
+            4 => 1.0 - (1.0 - linear * linear).max(0.0).sqrt(),
+            _ => linear,
+        };
+
+        match self.model {
+            1 | 2 => {
+                let [h0, s0, v0] = rgb_to_hsv(self.left_color);
+                let [mut h1, s1, v1] = rgb_to_hsv(self.right_color);
+                // Counter-clockwise (1) never decreases hue, clockwise (2) never
+                // increases it.
+                if self.model == 1 && h1 < h0 {
+                    h1 += 1.0;
+                } else if self.model == 2 && h1 > h0 {
+                    h1 -= 1.0;
+                }
+                let [h, s, v] = lerp([h0, s0, v0], [h1, s1, v1], factor);
+                hsv_to_rgb([h.rem_euclid(1.0), s, v])
+            }
+            _ => lerp(self.left_color, self.right_color, factor),
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn lerp(a: [f64; 3], b: [f64; 3], t: f64) -> [f64; 3] {
+    [
+        a[0] + (b[0] - a[0]) * t,
+        a[1] + (b[1] - a[1]) * t,
+        a[2] + (b[2] - a[2]) * t,
+    ]
+}
+
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f64) -> f64 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn linear_to_oklab([r, g, b]: [f64; 3]) -> [f64; 3] {
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+fn oklab_to_linear([lightness, a, b]: [f64; 3]) -> [f64; 3] {
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+
+    [
+        4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+        -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+        -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+    ]
+}
+
+fn rgb_to_hsv([r, g, b]: [f64; 3]) -> [f64; 3] {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6.0) / 6.0
+    } else if max == g {
+        ((b - r) / delta + 2.0) / 6.0
+    } else {
+        ((r - g) / delta + 4.0) / 6.0
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+
+    [hue, saturation, max]
+}
+
+fn hsv_to_rgb([hue, saturation, value]: [f64; 3]) -> [f64; 3] {
+    let c = value * saturation;
+    let h = hue * 6.0;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+
+    let [r, g, b] = if h < 1.0 {
+        [c, x, 0.0]
+    } else if h < 2.0 {
+        [x, c, 0.0]
+    } else if h < 3.0 {
+        [0.0, c, x]
+    } else if h < 4.0 {
+        [0.0, x, c]
+    } else if h < 5.0 {
+        [x, 0.0, c]
+    } else {
+        [c, 0.0, x]
+    };
+
+    let m = value - c;
+    [r + m, g + m, b + m]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: [f64; 3], expected: [f64; 3]) {
+        let error = (0..3)
+            .map(|i| (actual[i] - expected[i]).abs())
+            .fold(0.0, f64::max);
+        assert!(error < 1.0e-6, "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn parses_map_files() {
+        let palette = Palette::parse_map("255 0 0 red\n\n0 0 255 blue\n").unwrap();
+        assert_close(palette.color(0.0), [1.0, 0.0, 0.0]);
+        assert_close(palette.color(0.25), [0.5, 0.0, 0.5]);
+        assert_close(palette.color(0.5), [0.0, 0.0, 1.0]);
+        // Cyclic, so the last entry blends back into the first.
+        assert_close(palette.color(0.75), [0.5, 0.0, 0.5]);
+
+        for text in [
+            "",
+            "255 0\n",
+            "256 0 0\n",
+            "red green blue\n",
+            "0 0 0\n-1 0 0\n",
+        ] {
+            let error = Palette::parse_map(text).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn parses_ggr_files() {
+        let gradient = |segment: &str| {
+            Palette::parse_ggr(&format!("GIMP Gradient\nName: test\n1\n{}\n", segment))
+        };
+
+        // Linear RGB blend from black to white, in sRGB.
+        let palette = gradient("0 0.5 1 0 0 0 1 1 1 1 1 0 0").unwrap();
+        assert_eq!(palette.wrap, Wrap::Clamped);
+        assert_close(palette.color(0.5), [srgb_to_linear(0.5); 3]);
+        // The midpoint moves where the blend is half way.
+        let palette = gradient("0 0.25 1 0 0 0 1 1 1 1 1 0 0").unwrap();
+        assert_close(palette.color(0.25), [srgb_to_linear(0.5); 3]);
+
+        // HSV from red to blue passes green counter-clockwise and magenta
+        // clockwise.
+        let palette = gradient("0 0.5 1 1 0 0 1 0 0 1 1 0 1").unwrap();
+        assert_close(palette.color(0.5), [0.0, 1.0, 0.0]);
+        let palette = gradient("0 0.5 1 1 0 0 1 0 0 1 1 0 2").unwrap();
+        assert_close(palette.color(0.5), [1.0, 0.0, 1.0]);
+
+        for text in [
+            "",
+            "1\n0 0.5 1 0 0 0 1 1 1 1 1 0 0\n",
+            "GIMP Gradient\nName: test\n",
+            "GIMP Gradient\n0\n",
+            "GIMP Gradient\n2\n0 0.5 1 0 0 0 1 1 1 1 1 0 0\n",
+            "GIMP Gradient\n1\n0 0.5 1 0 0 0 1 1 1 1 1 0\n",
+            "GIMP Gradient\n1\n0 0.5 1 0 0 0 1 1 x 1 1 0 0\n",
+        ] {
+            let error = Palette::parse_ggr(text).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn interpolates_and_wraps_positions() {
+        // Black at 0 and white at 0.5.
+        let mut palette = Palette::builtin("grayscale").unwrap();
+        assert_close(palette.color(0.25), [0.5; 3]);
+        assert_close(palette.color(0.75), [0.5; 3]);
+        assert_close(palette.color(1.25), [0.5; 3]);
+        assert_close(palette.color(-0.25), [0.5; 3]);
+
+        // Half way in OKLab is half the lightness, not half the light.
+        palette.interpolation = Interpolation::Oklab;
+        assert_close(palette.color(0.25), [0.125; 3]);
+        palette.interpolation = Interpolation::LinearRgb;
+
+        palette.wrap = Wrap::Clamped;
+        assert_close(palette.color(0.75), [1.0; 3]);
+        assert_close(palette.color(1.25), [1.0; 3]);
+        assert_close(palette.color(-0.25), [0.0; 3]);
+
+        palette.offset = 0.25;
+        palette.density = 2.0;
+        assert_close(palette.color(0.0), [0.5; 3]);
+        assert_close(palette.color(0.125), [1.0; 3]);
+        assert_close(palette.color(-0.125), [0.0; 3]);
+    }
+
+    #[test]
+    fn lookup_table_matches_color() {
+        for name in Palette::BUILTIN_NAMES {
+            for interpolation in [Interpolation::LinearRgb, Interpolation::Oklab] {
+                let palette = Palette {
+                    interpolation,
+                    ..Palette::builtin(name).unwrap()
+                };
+                let table = palette.lookup_table(64);
+                assert_eq!(table.len(), 65);
+                for (i, entry) in table.iter().enumerate().take(64) {
+                    let expected = palette.color(i as f64 / 64.0).map(|c| c as f32);
+                    assert_eq!(entry[..3], expected, "{} at {}", name, i);
+                    assert_eq!(entry[3], 1.0);
+                }
+            }
+        }
+    }
+}
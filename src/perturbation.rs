@@ -0,0 +1,450 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use image::RgbImage;
+
+use crate::complex::Complex;
+use crate::fixed::Fixed;
+use crate::float_exp::FloatExp;
+use crate::formula::Quadratic;
+use crate::fractal::{self, Derivative, Escape, Exit, Kernel, RenderOptions, RenderStats};
+use crate::real::Real;
+use crate::viewport::Viewport;
+
+/// Bits of precision kept beyond the pixel spacing for the reference orbit.
+const GUARD_BITS: u32 = 64;
+
+/// Pauldelbrot's glitch criterion, squared: a pixel whose full orbit value
+/// falls below this fraction of the reference's magnitude has lost its
+/// significant digits to cancellation.
+const GLITCH_TOLERANCE_SQR: f64 = 1.0e-6;
+
+/// The reference orbit is stored in fixed point with a 32-bit integer part,
+/// so its own escape radius is capped well below that.
+const MAX_REFERENCE_BAILOUT: f64 = 1.0e4;
+
+/// Terms kept in the series approximation; one more is computed to bound the
+/// truncation error.
+const SERIES_TERMS: usize = 8;
+
+/// Largest truncation error the series approximation may introduce, in
+/// pixels.
+const SERIES_TOLERANCE: f64 = 1.0e-3;
+
+/// Deltas whose squared magnitude is below this are iterated in `FloatExp`,
+/// since `f64` would underflow them; above it plain `f64` takes over.
+const EXTENDED_RANGE_LIMIT_SQR: f64 = 1.0e-300;
+
+/// Spans below `2^-SCALED_SPAN_BITS` are measured in scaled units.
+const SCALED_SPAN_BITS: f64 = 900.0;
+
+/// Spans below `2^-MAX_DEPTH_BITS` are refused: each reference orbit step
+/// grows with the square of the precision, so they could never finish.
+pub const MAX_DEPTH_BITS: f64 = 100_000.0;
+
+/// View center given to arbitrary precision.
+///
+/// Offsets within the view are measured in units of `2^scale`, so views
+/// narrower than `f64` can represent still get an ordinary `Viewport`.
+pub struct DeepCenter {
+    re: Fixed,
+    im: Fixed,
+    scale: i64,
+}
+
+impl DeepCenter {
+    /// Parses decimal coordinates for a view whose shorter side spans `span`,
+    /// returning the center and the viewport to render it with.
+    pub fn parse(
+        re: &str,
+        im: &str,
+        span: FloatExp,
+        width: u32,
+        height: u32,
+    ) -> Option<(DeepCenter, Viewport)> {
+        let scale = match span.log2() < -SCALED_SPAN_BITS {
+            true => span.log2().floor() as i64,
+            false => 0,
+        };
+        let pixel_size_log2 = span.log2() - (width.min(height) as f64).log2();
+        let limbs = Fixed::limbs_for(pixel_size_log2, GUARD_BITS)?;
+        let center = DeepCenter {
+            re: Fixed::parse(re, limbs)?,
+            im: Fixed::parse(im, limbs)?,
+            scale,
+        };
+
+        // The viewport's own center is only used for display.
+        let approximate = Complex::new(re.parse().ok()?, im.parse().ok()?);
+        let units = span.mul_pow2(-scale).to_f64();
+        Some((center, Viewport::new(approximate, units, width, height)))
+    }
+}
+
+/// The orbit of the view center, iterated in high precision and stored as
+/// `f64`. Pixels iterate only their small offset from it.
+struct ReferenceOrbit {
+    orbit: Vec<Complex>,
+}
+
+/// A pixel's progress through `ReferenceOrbit::iterate`.
+struct Perturbed<D> {
+    delta: Complex<D>,
+    /// Derivative with respect to the pixel offset in view units.
+    dz: Complex<D>,
+    index: usize,
+    iterations: u64,
+}
+
+impl<D: Real> Perturbed<D> {
+    fn to_f64(&self) -> Perturbed<f64> {
+        Perturbed {
+            delta: self.delta.to_f64(),
+            dz: self.dz.to_f64(),
+            index: self.index,
+            iterations: self.iterations,
+        }
+    }
+}
+
+impl ReferenceOrbit {
+    fn compute(center: &DeepCenter, max_iterations: u64, bailout: f64) -> Self {
+        let bailout = bailout.min(MAX_REFERENCE_BAILOUT);
+        let bailout_sqr = bailout * bailout;
+        let limbs = center.re.fraction_limbs();
+        let two = Fixed::parse("2", limbs).unwrap();
+
+        let mut re = Fixed::zero(limbs);
+        let mut im = Fixed::zero(limbs);
+        let mut orbit = vec![Complex::ZERO];
+
+        for _ in 0..max_iterations {
+            let re_sqr = re.square();
+            let im_sqr = im.square();
+            let cross = &(&re * &im) * &two;
+            re = &(&re_sqr - &im_sqr) + &center.re;
+            im = &cross + &center.im;
+
+            let z = Complex::new(re.to_f64(), im.to_f64());
+            orbit.push(z);
+            if z.norm_sqr() > bailout_sqr {
+                break;
+            }
+        }
+
+        ReferenceOrbit { orbit }
+    }
+
+    /// Iterates the pixel at `offset` view units from the reference, starting
+    /// where `series` leaves off. Deltas too small for `f64` are iterated in
+    /// `FloatExp` until they have grown into its range.
+    fn escape(
+        &self,
+        kernel: &Kernel,
+        series: &SeriesApproximation,
+        offset: Complex,
+        scale: i64,
+        counters: &Counters,
+    ) -> Escape {
+        let bailout_sqr = kernel.bailout * kernel.bailout;
+        let unit = FloatExp::ONE.mul_pow2(scale);
+        let dc = Complex::new(
+            FloatExp::from_f64(offset.re).mul_pow2(scale),
+            FloatExp::from_f64(offset.im).mul_pow2(scale),
+        );
+
+        let (delta, dz) = series.evaluate(offset);
+        let mut pixel = Perturbed {
+            delta,
+            dz,
+            index: series.iterations,
+            iterations: series.iterations as u64,
+        };
+        if (self.orbit[pixel.index] + delta.to_f64()).norm_sqr() > bailout_sqr {
+            // Escaped during the skipped iterations; only possible for
+            // pixels far outside the set, which are cheap to iterate.
+            pixel.delta = Complex::ZERO;
+            pixel.dz = Complex::ZERO;
+            pixel.index = 0;
+            pixel.iterations = 0;
+        }
+        counters
+            .skipped
+            .fetch_add(pixel.iterations, Ordering::Relaxed);
+
+        let limit = FloatExp::from_f64(EXTENDED_RANGE_LIMIT_SQR);
+        if dc.norm_sqr() < limit && pixel.delta.norm_sqr() < limit {
+            let escape = self.iterate(kernel, dc, unit, &mut pixel, Some(limit), counters);
+            if let Some(escape) = escape {
+                return escape;
+            }
+        }
+
+        let mut pixel = pixel.to_f64();
+        self.iterate(
+            kernel,
+            dc.to_f64(),
+            unit.to_f64(),
+            &mut pixel,
+            None,
+            counters,
+        )
+        .unwrap()
+    }
+
+    /// Advances `pixel` as `d' = 2 Z d + d^2 + dc` in number type `D`.
+    /// Returns `None` once `|d|^2` exceeds `limit`, leaving `pixel` at the
+    /// iteration where that happened.
+    ///
+    /// Whenever the full value `Z + d` becomes smaller than `d` itself, or
+    /// trips the glitch criterion, or the reference runs out, the pixel is
+    /// rebased: `d` becomes the full value and iteration continues from the
+    /// start of the reference, which is always `0`.
+    fn iterate<D: Real>(
+        &self,
+        kernel: &Kernel,
+        dc: Complex<D>,
+        unit: D,
+        pixel: &mut Perturbed<D>,
+        limit: Option<D>,
+        counters: &Counters,
+    ) -> Option<Escape> {
+        let bailout_sqr = kernel.bailout * kernel.bailout;
+        let last = self.orbit.len() - 1;
+
+        while pixel.iterations < kernel.max_iterations {
+            let z = Complex::from_f64(self.orbit[pixel.index]) + pixel.delta;
+            let z_norm_sqr = z.to_f64().norm_sqr();
+            if z_norm_sqr > bailout_sqr {
+                return Some(Escape {
+                    iterations: pixel.iterations,
+                    z: z.to_f64(),
+                    derivative: pixel.dz.to_f64(),
+                    exit: Exit::Escaped,
+                });
+            }
+            let delta_norm_sqr = pixel.delta.norm_sqr();
+            if limit.is_some_and(|limit| delta_norm_sqr > limit) {
+                return None;
+            }
+
+            if z.norm_sqr() < delta_norm_sqr || pixel.index == last {
+                counters.rebases.fetch_add(1, Ordering::Relaxed);
+                pixel.delta = z;
+                pixel.index = 0;
+            } else if z_norm_sqr < GLITCH_TOLERANCE_SQR * self.orbit[pixel.index].norm_sqr() {
+                counters.glitches.fetch_add(1, Ordering::Relaxed);
+                pixel.delta = z;
+                pixel.index = 0;
+            }
+
+            if kernel.derivative != Derivative::None {
+                pixel.dz = z.scale(2.0) * pixel.dz + Complex::new(unit, D::ZERO);
+            }
+            let reference = Complex::from_f64(self.orbit[pixel.index]);
+            pixel.delta = reference.scale(2.0) * pixel.delta + pixel.delta.square() + dc;
+            pixel.index += 1;
+            pixel.iterations += 1;
+        }
+
+        Some(Escape {
+            iterations: kernel.max_iterations,
+            z: (Complex::from_f64(self.orbit[pixel.index]) + pixel.delta).to_f64(),
+            derivative: pixel.dz.to_f64(),
+            exit: Exit::MaxIterations,
+        })
+    }
+}
+
+/// Truncated power series `d_n = a_1 dc + a_2 dc^2 + ...` shared by every
+/// pixel of the view, valid up to iteration `iterations`.
+///
+/// Coefficients are stored scaled as `a_k r^k`, where `r` bounds `|dc|` over
+/// the view, and in `FloatExp`, so they stay representable however deep the
+/// zoom. The series is advanced with the same recurrence as the orbit until
+/// the first omitted term could move a pixel by more than `SERIES_TOLERANCE`
+/// pixels.
+struct SeriesApproximation {
+    iterations: usize,
+    /// `r` in view units.
+    radius: f64,
+    coefficients: [Complex<FloatExp>; SERIES_TERMS],
+}
+
+impl SeriesApproximation {
+    /// A series that skips nothing.
+    fn none() -> Self {
+        SeriesApproximation {
+            iterations: 0,
+            radius: 1.0,
+            coefficients: [Complex::ZERO; SERIES_TERMS],
+        }
+    }
+
+    /// `radius` and `pixel_size` are in view units of `2^scale`.
+    fn compute(orbit: &[Complex], radius: f64, pixel_size: f64, scale: i64) -> Self {
+        let mut series = SeriesApproximation::none();
+        series.radius = radius;
+        let mut terms = [Complex::ZERO; SERIES_TERMS + 1];
+        let scaled_radius = FloatExp::from_f64(radius).mul_pow2(scale);
+        let tolerance = SERIES_TOLERANCE * pixel_size / radius;
+        let tolerance_sqr = FloatExp::from_f64(tolerance * tolerance);
+
+        // The last reference point is kept so a pixel can take at least one
+        // step before rebasing.
+        for (n, &z) in orbit[..orbit.len() - 1].iter().enumerate() {
+            let z = Complex::<FloatExp>::from_f64(z).scale(2.0);
+            let mut next = [Complex::ZERO; SERIES_TERMS + 1];
+            for k in 0..=SERIES_TERMS {
+                next[k] = z * terms[k];
+                for i in 0..k {
+                    next[k] = next[k] + terms[i] * terms[k - 1 - i];
+                }
+            }
+            next[0] = next[0] + Complex::new(scaled_radius, FloatExp::ZERO);
+
+            // A pixel spacing at the view maps to |a_1| pixel_size at this
+            // iteration, so the omitted term must stay well below that.
+            let error_sqr = next[SERIES_TERMS].norm_sqr();
+            let allowed_sqr = next[0].norm_sqr() * tolerance_sqr;
+            if error_sqr > allowed_sqr || error_sqr.to_f64().is_nan() {
+                break;
+            }
+
+            terms = next;
+            series.iterations = n + 1;
+            series.coefficients.copy_from_slice(&terms[..SERIES_TERMS]);
+        }
+
+        series
+    }
+
+    /// The delta at `iterations` for the pixel at `offset` view units, and
+    /// its derivative with respect to `offset`.
+    fn evaluate(&self, offset: Complex) -> (Complex<FloatExp>, Complex<FloatExp>) {
+        let u = Complex::<FloatExp>::from_f64(offset.scale(1.0 / self.radius));
+        let mut delta = Complex::ZERO;
+        let mut derivative = Complex::ZERO;
+        for (k, &coefficient) in self.coefficients.iter().enumerate().rev() {
+            delta = delta * u + coefficient;
+            if k > 0 {
+                derivative = derivative * u + coefficient.scale((k + 1) as f64);
+            }
+        }
+        let derivative = derivative * u + self.coefficients[0];
+        (delta * u, derivative.scale(1.0 / self.radius))
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    rebases: AtomicU64,
+    glitches: AtomicU64,
+    skipped: AtomicU64,
+}
+
+/// Renders the quadratic Mandelbrot set around a high-precision `center`
+/// using perturbation theory, for zooms beyond the reach of `f64`.
+/// `viewport` comes from `DeepCenter::parse`; its center is only used for
+/// display, and all pixels are placed relative to `center`.
+pub fn mandelbrot(
+    center: &DeepCenter,
+    viewport: &Viewport,
+    options: &RenderOptions,
+) -> (RgbImage, RenderStats) {
+    let kernel = Kernel::new(&Quadratic, options, Derivative::Parameter);
+    let reference = ReferenceOrbit::compute(center, kernel.max_iterations, kernel.bailout);
+    let series = match options.series_approximation {
+        true => {
+            let pixel_size = viewport.pixel_size();
+            let radius = pixel_size * (viewport.width as f64).hypot(viewport.height as f64) / 2.0;
+            SeriesApproximation::compute(&reference.orbit, radius, pixel_size, center.scale)
+        }
+        false => SeriesApproximation::none(),
+    };
+    let counters = Counters::default();
+
+    let (img, mut stats) = fractal::render(viewport, options, 2.0, |offset| {
+        reference.escape(&kernel, &series, offset, center.scale, &counters)
+    });
+
+    stats.reference_iterations = reference.orbit.len() as u64 - 1;
+    stats.rebases = counters.rebases.into_inner();
+    stats.glitches = counters.glitches.into_inner();
+    stats.skipped = counters.skipped.into_inner();
+    (img, stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Number of pixels whose colors differ by more than rounding.
+    fn differing(a: &RgbImage, b: &RgbImage) -> usize {
+        a.pixels()
+            .zip(b.pixels())
+            .filter(|(a, b)| a.0.iter().zip(b.0).any(|(&x, y)| x.abs_diff(y) > 2))
+            .count()
+    }
+
+    /// Perturbed and direct renders agree away from the limits of `f64`.
+    #[test]
+    fn matches_direct_rendering_at_shallow_zoom() {
+        for (re, im, span) in [("-0.5", "0", 2.0), ("-0.7435669", "0.1314023", 1.0e-4)] {
+            let span = FloatExp::from_f64(span);
+            let (deep, viewport) = DeepCenter::parse(re, im, span, 96, 54).unwrap();
+            let mut options = RenderOptions::new(2000);
+            options.interior_checks = false;
+
+            let (perturbed, stats) = mandelbrot(&deep, &viewport, &options);
+            let (direct, _) = fractal::mandelbrot(&Quadratic, &viewport, &options);
+
+            let differing = differing(&perturbed, &direct);
+            assert!(
+                differing * 100 < perturbed.len() / 3,
+                "{} pixels differ",
+                differing
+            );
+            assert!(stats.reference_iterations > 0);
+        }
+    }
+
+    #[test]
+    fn series_approximation_does_not_change_image() {
+        let (re, im) = (
+            "-0.743643887037158704752191506114774",
+            "0.131825904205311970493132056385139",
+        );
+        let span = FloatExp::from_f64(1.0e-10);
+        let (deep, viewport) = DeepCenter::parse(re, im, span, 64, 36).unwrap();
+        let mut options = RenderOptions::new(4000);
+        options.coloring = crate::coloring::Coloring::Distance { thickness: 1.0 };
+        options.bailout = Some(1000.0);
+
+        let (skipped, stats) = mandelbrot(&deep, &viewport, &options);
+        options.series_approximation = false;
+        let (full, _) = mandelbrot(&deep, &viewport, &options);
+
+        assert!(stats.skipped > 0);
+        assert_eq!(differing(&skipped, &full), 0);
+    }
+
+    /// Distance coloring is measured in pixels, so the self-similar view of
+    /// the tip at `-2` looks the same at any depth, including far below the
+    /// range of `f64`.
+    #[test]
+    fn renders_spans_below_f64_range() {
+        let mut options = RenderOptions::new(10_000);
+        options.coloring = crate::coloring::Coloring::Distance { thickness: 1.0 };
+        options.bailout = Some(1000.0);
+
+        let render = |span: &str| {
+            let span = FloatExp::parse_decimal(span).unwrap();
+            let (center, viewport) = DeepCenter::parse("-2", "0", span, 48, 32).unwrap();
+            mandelbrot(&center, &viewport, &options).0
+        };
+        let shallow = render("1e-100");
+        let deep = render("1e-400");
+        assert_eq!(differing(&shallow, &deep), 0);
+        assert!(deep.pixels().any(|pixel| pixel.0 != deep.get_pixel(0, 0).0));
+    }
+}
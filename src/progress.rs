@@ -0,0 +1,120 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::Instant;
+
+use image::RgbImage;
+
+use crate::tiles::Tile;
+
+/// Roughly how many `Progress::Work` updates a render sends.
+const REPORTS: u64 = 1000;
+
+const BAR_WIDTH: usize = 40;
+
+/// Sent through `RenderOptions::progress` while a render runs.
+#[derive(Debug, Clone)]
+pub enum Progress {
+    /// `done` of `total` pixels finished. Adaptive sampling grows `total`
+    /// by the number of pixels it refines once it knows it.
+    Work { done: u64, total: u64 },
+    /// A pass has finished `tile`. Progressive and adaptive renders pass
+    /// over every tile more than once. The last pass of a progressive render
+    /// includes the tile's final colors unless the coloring needs the whole
+    /// frame.
+    Tile { tile: Tile, image: Option<RgbImage> },
+    /// A coarse pass in which only every `step`-th pixel along each axis has
+    /// been computed, each standing in for its `step` x `step` block.
+    Preview { step: u32, image: RgbImage },
+}
+
+/// Counts finished work across render threads and forwards it, throttled, to
+/// the progress channel, if there is one.
+pub struct Reporter<'a> {
+    sender: Option<&'a Sender<Progress>>,
+    done: AtomicU64,
+    total: AtomicU64,
+}
+
+impl<'a> Reporter<'a> {
+    pub fn new(sender: Option<&'a Sender<Progress>>, total: u64) -> Self {
+        Reporter {
+            sender,
+            done: AtomicU64::new(0),
+            total: AtomicU64::new(total),
+        }
+    }
+
+    pub fn grow(&self, extra: u64) {
+        self.total.fetch_add(extra, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, amount: u64) {
+        let Some(sender) = self.sender else {
+            return;
+        };
+        let total = self.total.load(Ordering::Relaxed);
+        let before = self.done.fetch_add(amount, Ordering::Relaxed);
+        let done = before + amount;
+        let interval = (total / REPORTS).max(1);
+        if done / interval != before / interval || done == total {
+            // Nobody listening is not a reason to stop rendering.
+            let _ = sender.send(Progress::Work { done, total });
+        }
+    }
+
+    /// Counts the `pixels` of `tile` computed by a pass as done.
+    pub fn finish_tile(&self, tile: Tile, pixels: u64, image: Option<RgbImage>) {
+        if let Some(sender) = self.sender {
+            let _ = sender.send(Progress::Tile { tile, image });
+        }
+        self.advance(pixels);
+    }
+
+    pub fn preview(&self, step: u32, image: RgbImage) {
+        if let Some(sender) = self.sender {
+            let _ = sender.send(Progress::Preview { step, image });
+        }
+    }
+}
+
+/// Draws a progress bar with an estimated time remaining on stderr until the
+/// sending side of `receiver` is dropped.
+pub fn show_bar(receiver: Receiver<Progress>) {
+    let start = Instant::now();
+    let mut stderr = io::stderr();
+    let mut shown = false;
+    for progress in receiver {
+        let Progress::Work { done, total } = progress else {
+            continue;
+        };
+        let fraction = (done as f64 / total.max(1) as f64).min(1.0);
+        let elapsed = start.elapsed().as_secs_f64();
+        let remaining = elapsed * (1.0 - fraction) / fraction;
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+        let _ = write!(
+            stderr,
+            "\r[{}{}] {:5.1}%  ETA {:<7}",
+            "#".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            fraction * 100.0,
+            format_seconds(remaining)
+        );
+        shown = true;
+    }
+    if shown {
+        let _ = writeln!(stderr);
+    }
+}
+
+fn format_seconds(seconds: f64) -> String {
+    if !seconds.is_finite() {
+        return "--".to_string();
+    }
+    let seconds = seconds.round() as u64;
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{:02}m", seconds / 3600, seconds / 60 % 60),
+    }
+}
@@ -0,0 +1,302 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+
+use crate::fractal::{RenderOptions, RenderStats, ViewRender};
+use crate::progress::Reporter;
+use crate::tiles::Tile;
+use crate::viewport::Frame;
+
+/// How the tiles of a pyramid are laid out on disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    /// A `.dzi` descriptor next to a `<name>_files/<level>/<column>_<row>.png`
+    /// tree. Level `n` is `2^n` times smaller than the full image, rounded
+    /// up, down to a single pixel.
+    DeepZoom,
+    /// Slippy-map tiles in `<directory>/<z>/<x>/<y>.png`. Zoom 0 is a single
+    /// square tile covering the view's longer side; every zoom splits each
+    /// tile into four.
+    Xyz,
+}
+
+impl Layout {
+    pub const NAMES: &'static [&'static str] = &["dzi", "xyz"];
+
+    pub fn from_name(name: &str) -> Option<Layout> {
+        match name {
+            "dzi" => Some(Layout::DeepZoom),
+            "xyz" => Some(Layout::Xyz),
+            _ => None,
+        }
+    }
+
+    /// The tile size viewers expect by default.
+    pub fn default_tile_size(self) -> u32 {
+        match self {
+            Layout::DeepZoom => 254,
+            Layout::Xyz => 256,
+        }
+    }
+}
+
+/// A tile pyramid of a view, every level rendered at its own resolution.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pyramid {
+    pub layout: Layout,
+    pub tile_size: u32,
+    /// Pixels each Deep Zoom tile shares with its neighbours on every side
+    /// that has one. Slippy-map tiles do not overlap.
+    pub overlap: u32,
+}
+
+/// One level of the pyramid: the view drawn at `frame`, cut into tiles.
+struct Level {
+    frame: Frame,
+    tiles: Vec<(PathBuf, Tile)>,
+}
+
+impl Pyramid {
+    /// Renders the pyramid of a `width x height` view through `render` and
+    /// writes it to `path`: the `.dzi` file for Deep Zoom, the top directory
+    /// for slippy-map tiles.
+    pub fn export(
+        &self,
+        path: &Path,
+        width: u32,
+        height: u32,
+        options: &RenderOptions,
+        render: &ViewRender,
+    ) -> io::Result<RenderStats> {
+        let levels = match self.layout {
+            Layout::DeepZoom => {
+                fs::write(path, self.descriptor(width, height))?;
+                let mut tiles = path.with_extension("").into_os_string();
+                tiles.push("_files");
+                self.deep_zoom_levels(Path::new(&tiles), width, height)
+            }
+            Layout::Xyz => self.xyz_levels(path, width, height),
+        };
+
+        let pixels = |level: &Level| -> u64 {
+            let tiles = level.tiles.iter();
+            tiles
+                .map(|(_, tile)| tile.width as u64 * tile.height as u64)
+                .sum()
+        };
+        let reporter = Reporter::new(options.progress.as_ref(), levels.iter().map(pixels).sum());
+        let mut tile_options = options.clone();
+        tile_options.progress = None;
+
+        let mut stats = RenderStats::default();
+        for level in &levels {
+            let level_stats = level
+                .tiles
+                .par_iter()
+                .map(|(tile_path, tile)| -> io::Result<RenderStats> {
+                    if options.cancel.is_cancelled() {
+                        return Ok(RenderStats {
+                            cancelled: true,
+                            ..RenderStats::default()
+                        });
+                    }
+                    let mut tile_options = tile_options.clone();
+                    tile_options.crop = Some(*tile);
+                    let (image, stats) = render(level.frame, &tile_options);
+                    if !stats.cancelled {
+                        fs::create_dir_all(tile_path.parent().unwrap())?;
+                        image.save(tile_path).map_err(io::Error::other)?;
+                    }
+                    reporter.advance(tile.width as u64 * tile.height as u64);
+                    Ok(stats)
+                })
+                .try_reduce(RenderStats::default, |a, b| Ok(a + b))?;
+            stats = stats + level_stats;
+        }
+        Ok(stats)
+    }
+
+    fn descriptor(&self, width: u32, height: u32) -> String {
+        format!(
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" ",
+                "Format=\"png\" Overlap=\"{}\" TileSize=\"{}\">\n",
+                "  <Size Width=\"{}\" Height=\"{}\"/>\n",
+                "</Image>\n"
+            ),
+            self.overlap, self.tile_size, width, height
+        )
+    }
+
+    fn deep_zoom_levels(&self, directory: &Path, width: u32, height: u32) -> Vec<Level> {
+        let top = width.max(height).next_power_of_two().trailing_zeros();
+        (0..=top)
+            .map(|level| {
+                let shrink = 1u64 << (top - level);
+                let size = |side: u32| (side as u64).div_ceil(shrink) as u32;
+                let (level_width, level_height) = (size(width), size(height));
+                // Keep the pixel size exactly `shrink` times the full image's,
+                // and the top left corner where the full image's is: rounding
+                // the level size up extends it past the right and bottom edges.
+                let span_scale = (shrink * level_width.min(level_height) as u64) as f64
+                    / width.min(height) as f64;
+                let overhang = |level_side: u32, side: u32| {
+                    (level_side as u64 * shrink - side as u64) as f64 / (2 * shrink) as f64
+                };
+                let shift = [overhang(level_width, width), overhang(level_height, height)];
+
+                let size = self.tile_size as u64;
+                let overlap = self.overlap as u64;
+                let mut tiles = Vec::new();
+                for column in 0..(level_width as u64).div_ceil(size) {
+                    for row in 0..(level_height as u64).div_ceil(size) {
+                        let x = (column * size).saturating_sub(overlap);
+                        let y = (row * size).saturating_sub(overlap);
+                        let right = ((column + 1) * size + overlap).min(level_width as u64);
+                        let bottom = ((row + 1) * size + overlap).min(level_height as u64);
+                        let name = format!("{}/{}_{}.png", level, column, row);
+                        let tile = Tile {
+                            x: x as u32,
+                            y: y as u32,
+                            width: (right - x) as u32,
+                            height: (bottom - y) as u32,
+                        };
+                        tiles.push((directory.join(name), tile));
+                    }
+                }
+                let frame = Frame {
+                    width: level_width,
+                    height: level_height,
+                    span_scale,
+                    shift,
+                };
+                Level { frame, tiles }
+            })
+            .collect()
+    }
+
+    /// Zoom levels up to the first whose tiles are as fine as the full
+    /// `width x height` image.
+    fn xyz_levels(&self, directory: &Path, width: u32, height: u32) -> Vec<Level> {
+        let longer = width.max(height);
+        let top = longer
+            .div_ceil(self.tile_size)
+            .next_power_of_two()
+            .trailing_zeros();
+        let span_scale = longer as f64 / width.min(height) as f64;
+        (0..=top)
+            .map(|zoom| {
+                let count = 1u32 << zoom;
+                let side = self.tile_size * count;
+                let mut tiles = Vec::new();
+                for x in 0..count {
+                    for y in 0..count {
+                        let name = format!("{}/{}/{}.png", zoom, x, y);
+                        let tile = Tile {
+                            x: x * self.tile_size,
+                            y: y * self.tile_size,
+                            width: self.tile_size,
+                            height: self.tile_size,
+                        };
+                        tiles.push((directory.join(name), tile));
+                    }
+                }
+                let frame = Frame {
+                    width: side,
+                    height: side,
+                    span_scale,
+                    shift: [0.0, 0.0],
+                };
+                Level { frame, tiles }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::complex::Complex;
+    use crate::formula::Quadratic;
+    use crate::fractal;
+    use crate::viewport::Viewport;
+
+    #[test]
+    fn deep_zoom_tiles_match_levels_rendered_whole() {
+        let viewport = Viewport::new(Complex::new(-0.75, 0.0), 2.5, 300, 200);
+        let options = RenderOptions::new(200);
+        let render = |frame, options: &RenderOptions| {
+            fractal::mandelbrot(&Quadratic, &viewport.framed(frame), options)
+        };
+        let pyramid = Pyramid {
+            layout: Layout::DeepZoom,
+            tile_size: 64,
+            overlap: 2,
+        };
+
+        let directory = std::env::temp_dir().join(format!("pyramid-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("view.dzi");
+        pyramid.export(&path, 300, 200, &options, &render).unwrap();
+        assert!(
+            fs::read_to_string(&path)
+                .unwrap()
+                .contains("Width=\"300\" Height=\"200\"")
+        );
+
+        // Level 9 is the full image, level 8 half of it rounded up, and level
+        // 0 a single pixel.
+        let levels = pyramid.deep_zoom_levels(&directory.join("view_files"), 300, 200);
+        assert_eq!(levels.len(), 10);
+        assert_eq!((levels[8].frame.width, levels[8].frame.height), (150, 100));
+        assert_eq!((levels[0].frame.width, levels[0].frame.height), (1, 1));
+        for level in &levels[7..] {
+            let (whole, _) = render(level.frame, &options);
+            for (tile_path, tile) in &level.tiles {
+                let expected =
+                    image::imageops::crop_imm(&whole, tile.x, tile.y, tile.width, tile.height)
+                        .to_image();
+                assert_eq!(image::open(tile_path).unwrap().to_rgb8(), expected);
+            }
+        }
+        // Every level's pixels cover the same area of the full image as the
+        // `shrink x shrink` block of full-resolution pixels below them.
+        for (level, shrink) in levels
+            .iter()
+            .zip((0..10).rev().map(|level| (1 << level) as f64))
+        {
+            let framed = viewport.framed(level.frame);
+            let corner = framed.pixel_offset(0.0, 0.0);
+            let far = framed.pixel_offset(level.frame.width as f64, level.frame.height as f64);
+            let expected = viewport.pixel_offset(0.0, 0.0);
+            let expected_far = viewport.pixel_offset(
+                level.frame.width as f64 * shrink,
+                level.frame.height as f64 * shrink,
+            );
+            for (a, b) in [(corner, expected), (far, expected_far)] {
+                assert!((a - b).norm() < 1e-12, "{:?} {:?}", a, b);
+            }
+        }
+
+        let first = image::open(directory.join("view_files/9/1_0.png")).unwrap();
+        assert_eq!((first.width(), first.height()), (64 + 4, 64 + 2));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn xyz_zooms_quarter_tiles() {
+        let pyramid = Pyramid {
+            layout: Layout::Xyz,
+            tile_size: 256,
+            overlap: 0,
+        };
+        let levels = pyramid.xyz_levels(Path::new("tiles"), 1000, 600);
+        assert_eq!(levels.len(), 3);
+        assert_eq!(levels[2].tiles.len(), 16);
+        assert_eq!(levels[2].frame.width, 1024);
+        assert_eq!(levels[2].tiles[5].0, Path::new("tiles/2/1/1.png"));
+    }
+}
@@ -0,0 +1,33 @@
+/// SplitMix64: a tiny generator with good statistical quality, used wherever
+/// a render needs randomness that is repeatable from a seed.
+#[derive(Debug, Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+    pub fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+
+    /// A generator for item `index` of a stream seeded with `seed`, so
+    /// parallel workers can draw independent, repeatable numbers.
+    pub fn for_index(seed: u64, index: u64) -> Self {
+        SplitMix64::new(seed ^ index.wrapping_mul(Self::GAMMA).rotate_left(32))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(Self::GAMMA);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,196 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::complex::Complex;
+use crate::fractal::{Escape, Exit};
+
+const MAGIC: &[u8; 8] = b"FRACTRAW";
+const VERSION: u32 = 1;
+const HEADER_BYTES: usize = 48;
+const RECORD_BYTES: usize = 56;
+
+/// The escape data of every sample of a render, from which any coloring can
+/// be applied again without iterating.
+///
+/// On disk, all little-endian, a 48-byte header:
+///
+/// | offset | type     | field                                    |
+/// |--------|----------|------------------------------------------|
+/// | 0      | [u8; 8]  | `FRACTRAW`                               |
+/// | 8      | u32      | format version, 1                        |
+/// | 12     | u32      | width                                    |
+/// | 16     | u32      | height                                   |
+/// | 20     | u32      | samples per pixel along each axis        |
+/// | 24     | u64      | max iterations                           |
+/// | 32     | f64      | formula degree                           |
+/// | 40     | f64      | sample spacing in complex-plane units    |
+///
+/// followed by one 56-byte record per sample, pixel by pixel in row-major
+/// order and sample by sample within each pixel:
+///
+/// | offset | type     | field                                    |
+/// |--------|----------|------------------------------------------|
+/// | 0      | f64      | smooth iteration count, NaN if interior  |
+/// | 8      | f64 x 2  | final z                                  |
+/// | 24     | f64 x 2  | derivative                               |
+/// | 40     | u64      | iterations                               |
+/// | 48     | u32      | exit: 0 escaped, 1 max iterations, 2 known interior, 3 periodic |
+/// | 52     | u32      | reserved, 0                              |
+///
+/// Any exit but 0 marks an interior sample.
+#[derive(Debug, Clone)]
+pub struct RawData {
+    pub width: u32,
+    pub height: u32,
+    pub supersample: u32,
+    pub max_iterations: u64,
+    pub degree: f64,
+    pub pixel_size: f64,
+    pub escapes: Vec<Escape>,
+}
+
+impl RawData {
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        for value in [VERSION, self.width, self.height, self.supersample] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        out.write_all(&self.max_iterations.to_le_bytes())?;
+        out.write_all(&self.degree.to_le_bytes())?;
+        out.write_all(&self.pixel_size.to_le_bytes())?;
+
+        for escape in &self.escapes {
+            let smooth = match escape.escaped() {
+                true => escape.smooth(self.degree),
+                false => f64::NAN,
+            };
+            let exit: u32 = match escape.exit {
+                Exit::Escaped => 0,
+                Exit::MaxIterations => 1,
+                Exit::KnownInterior => 2,
+                Exit::Periodic => 3,
+            };
+            let z = escape.z;
+            let dz = escape.derivative;
+            for value in [smooth, z.re, z.im, dz.re, dz.im] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            out.write_all(&escape.iterations.to_le_bytes())?;
+            out.write_all(&exit.to_le_bytes())?;
+            out.write_all(&0u32.to_le_bytes())?;
+        }
+        out.flush()
+    }
+
+    pub fn read(path: &Path) -> io::Result<RawData> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut input = BufReader::new(File::open(path)?);
+        let mut header = [0; HEADER_BYTES];
+        input.read_exact(&mut header)?;
+        let u32_at = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(header[at..at + 8].try_into().unwrap());
+        if &header[..8] != MAGIC {
+            return Err(invalid("not a raw iteration data file"));
+        }
+        if u32_at(8) != VERSION {
+            return Err(invalid("unsupported raw iteration data version"));
+        }
+        let (width, height, supersample) = (u32_at(12), u32_at(16), u32_at(20));
+        // Check the header against the file before trusting it with an
+        // allocation.
+        let samples = (width as u64)
+            .checked_mul(height as u64)
+            .and_then(|pixels| pixels.checked_mul((supersample as u64).checked_pow(2)?))
+            .ok_or_else(|| invalid("raw iteration data dimensions overflow"))?;
+        let length = input.get_ref().metadata()?.len();
+        if samples.checked_mul(RECORD_BYTES as u64) != Some(length - HEADER_BYTES as u64) {
+            return Err(invalid("raw iteration data does not match its dimensions"));
+        }
+        let samples = usize::try_from(samples)
+            .map_err(|_| invalid("raw iteration data dimensions overflow"))?;
+
+        let mut escapes = Vec::with_capacity(samples);
+        let mut record = [0; RECORD_BYTES];
+        for _ in 0..samples {
+            input.read_exact(&mut record)?;
+            let f64_at = |at: usize| f64::from_le_bytes(record[at..at + 8].try_into().unwrap());
+            let exit = match u32::from_le_bytes(record[48..52].try_into().unwrap()) {
+                0 => Exit::Escaped,
+                1 => Exit::MaxIterations,
+                2 => Exit::KnownInterior,
+                3 => Exit::Periodic,
+                _ => return Err(invalid("unknown exit in raw iteration data")),
+            };
+            escapes.push(Escape {
+                iterations: u64::from_le_bytes(record[40..48].try_into().unwrap()),
+                z: Complex::new(f64_at(8), f64_at(16)),
+                derivative: Complex::new(f64_at(24), f64_at(32)),
+                exit,
+            });
+        }
+
+        Ok(RawData {
+            width,
+            height,
+            supersample,
+            max_iterations: u64_at(24),
+            degree: f64::from_le_bytes(header[32..40].try_into().unwrap()),
+            pixel_size: f64::from_le_bytes(header[40..48].try_into().unwrap()),
+            escapes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::coloring::Coloring;
+    use crate::formula::Quadratic;
+    use crate::fractal::{self, RenderOptions};
+    use crate::viewport::Viewport;
+
+    #[test]
+    fn recolored_file_matches_direct_render() {
+        let viewport = Viewport::new(Complex::new(-0.75, 0.1), 0.1, 120, 90);
+        let mut options = RenderOptions::new(500);
+        options.supersample = 2;
+        let (direct, _) = fractal::mandelbrot(&Quadratic, &viewport, &options);
+
+        let (sender, receiver) = mpsc::channel();
+        options.raw = Some(sender);
+        let (captured, _) = fractal::mandelbrot(&Quadratic, &viewport, &options);
+        assert_eq!(captured, direct);
+
+        let path = std::env::temp_dir().join(format!("raw-{}.raw", std::process::id()));
+        receiver.recv().unwrap().write(&path).unwrap();
+        let raw = RawData::read(&path).unwrap();
+        assert_eq!(raw.escapes.len(), 120 * 90 * 4);
+
+        // Headers that do not match the records are refused before anything
+        // is allocated for them.
+        let mut bytes = fs::read(&path).unwrap();
+        for (at, value) in [(12, u32::MAX), (12, 121)] {
+            let mut corrupt = bytes.clone();
+            corrupt[at..at + 4].copy_from_slice(&value.to_le_bytes());
+            fs::write(&path, &corrupt).unwrap();
+            let error = RawData::read(&path).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+        bytes.truncate(bytes.len() - 1);
+        fs::write(&path, &bytes).unwrap();
+        assert!(RawData::read(&path).is_err());
+        fs::remove_file(&path).unwrap();
+
+        options.raw = None;
+        for coloring in [Coloring::Iterations, Coloring::Distance { thickness: 1.0 }] {
+            options.coloring = coloring;
+            let (expected, _) = fractal::mandelbrot(&Quadratic, &viewport, &options);
+            assert_eq!(fractal::recolor(&raw, &options), expected, "{:?}", coloring);
+        }
+    }
+}
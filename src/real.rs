@@ -0,0 +1,125 @@
+use std::fmt::Debug;
+use std::ops::{Add, Mul, Neg, Sub};
+
+use crate::double_double::DoubleDouble;
+
+/// Scalar type the escape-time kernels iterate in.
+///
+/// Only the orbit itself uses it; derivatives, colors and offsets within the
+/// view stay in `f64`, which has range and precision enough for them.
+pub trait Real:
+    Copy
+    + Debug
+    + Default
+    + PartialEq
+    + PartialOrd
+    + Send
+    + Sync
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Neg<Output = Self>
+{
+    const ZERO: Self;
+    const ONE: Self;
+    /// Spacing of representable numbers just above 1.
+    const EPSILON: f64;
+
+    fn from_f64(value: f64) -> Self;
+    fn to_f64(self) -> f64;
+    fn abs(self) -> Self;
+    /// Parses a decimal string without first rounding it to `f64`.
+    fn parse_decimal(text: &str) -> Option<Self>;
+}
+
+impl Real for f32 {
+    const ZERO: Self = 0.0;
+    const ONE: Self = 1.0;
+    const EPSILON: f64 = f32::EPSILON as f64;
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn abs(self) -> Self {
+        f32::abs(self)
+    }
+
+    fn parse_decimal(text: &str) -> Option<Self> {
+        text.trim().parse().ok()
+    }
+}
+
+impl Real for f64 {
+    const ZERO: Self = 0.0;
+    const ONE: Self = 1.0;
+    const EPSILON: f64 = f64::EPSILON;
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn abs(self) -> Self {
+        f64::abs(self)
+    }
+
+    fn parse_decimal(text: &str) -> Option<Self> {
+        text.trim().parse().ok()
+    }
+}
+
+/// Number type selected with `--precision`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Precision {
+    F32,
+    F64,
+    DoubleDouble,
+}
+
+impl Precision {
+    /// Accepted names, including `auto`, which `from_name` leaves to the
+    /// caller.
+    pub const NAMES: &'static [&'static str] = &["f32", "f64", "dd", "auto"];
+
+    pub fn from_name(name: &str) -> Option<Precision> {
+        match name {
+            "f32" => Some(Precision::F32),
+            "f64" => Some(Precision::F64),
+            "dd" | "double-double" => Some(Precision::DoubleDouble),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Precision::F32 => "f32",
+            Precision::F64 => "f64",
+            Precision::DoubleDouble => "dd",
+        }
+    }
+
+    pub fn epsilon(self) -> f64 {
+        match self {
+            Precision::F32 => <f32 as Real>::EPSILON,
+            Precision::F64 => <f64 as Real>::EPSILON,
+            Precision::DoubleDouble => <DoubleDouble as Real>::EPSILON,
+        }
+    }
+
+    /// The cheapest precision that still resolves neighbouring pixels of a
+    /// view with the given `pixel_size` around coordinates of `magnitude`,
+    /// or `None` if even double-double cannot.
+    pub fn auto(pixel_size: f64, magnitude: f64) -> Option<Precision> {
+        [Precision::F32, Precision::F64, Precision::DoubleDouble]
+            .into_iter()
+            .find(|precision| precision.epsilon() * magnitude.max(1.0) < pixel_size)
+    }
+}
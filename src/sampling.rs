@@ -0,0 +1,61 @@
+use crate::random::SplitMix64;
+
+/// Where the `n x n` samples of each pixel are placed by `fractal::render`.
+/// Sample colors are averaged in linear light.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampling {
+    /// A regular grid.
+    Grid,
+    /// A grid sheared so that no two samples share a row or column, which
+    /// resolves near-horizontal and near-vertical edges better.
+    RotatedGrid,
+    /// One uniformly random sample in each cell of the regular grid, drawn
+    /// from `seed` so renders are repeatable.
+    Jittered { seed: u64 },
+    /// One sample per pixel, then a rotated grid for pixels whose color
+    /// differs from a neighbour's by more than `threshold` in any sRGB
+    /// channel (0 to 1).
+    Adaptive { threshold: f64 },
+}
+
+impl Sampling {
+    pub const NAMES: &'static [&'static str] = &["grid", "rotated", "jittered", "adaptive"];
+
+    pub fn from_name(name: &str) -> Option<Sampling> {
+        match name {
+            "grid" => Some(Sampling::Grid),
+            "rotated" | "rotated-grid" => Some(Sampling::RotatedGrid),
+            "jittered" => Some(Sampling::Jittered { seed: 0 }),
+            "adaptive" => Some(Sampling::Adaptive { threshold: 0.1 }),
+            _ => None,
+        }
+    }
+
+    /// Position of sample `index` of `n * n` inside pixel `(x, y)`, relative
+    /// to the pixel's top-left corner. Adaptive sampling refines with the
+    /// rotated grid.
+    pub fn position(self, n: u32, x: u32, y: u32, index: u32) -> (f64, f64) {
+        let n_f = n as f64;
+        let column = (index % n) as f64;
+        let row = (index / n) as f64;
+
+        match self {
+            Sampling::Grid => ((column + 0.5) / n_f, (row + 0.5) / n_f),
+            Sampling::RotatedGrid | Sampling::Adaptive { .. } => (
+                (column + (row + 0.5) / n_f) / n_f,
+                (row + (column + 0.5) / n_f) / n_f,
+            ),
+            Sampling::Jittered { seed } => {
+                let pixel = (y as u64) << 32 | x as u64;
+                let sample = pixel
+                    .wrapping_mul((n * n) as u64)
+                    .wrapping_add(index as u64);
+                let mut random = SplitMix64::for_index(seed, sample);
+                (
+                    (column + random.next_f64()) / n_f,
+                    (row + random.next_f64()) / n_f,
+                )
+            }
+        }
+    }
+}
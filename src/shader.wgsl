@@ -1,19 +1,32 @@
 struct Uniforms {
-    center: vec2<f32>,
+    // The center is split into float-float hi/lo parts; the f32 path only
+    // reads `center_hi`.
+    center_hi: vec2<f32>,
+    center_lo: vec2<f32>,
     range: vec2<f32>,
     max_iter: i32,
+    rotation: f32,
+    palette_offset: f32,
+    palette_density: f32,
+    palette_cyclic: u32,
+    emulated: u32,
 };
 
 @group(0) @binding(0) var<uniform> uniforms: Uniforms;
+@group(0) @binding(1) var<storage, read> palette: array<vec4<f32>>;
 @group(1) @binding(0) var storage_texture: texture_storage_2d<rgba8unorm, write>;
 
+fn smooth_count(i: i32, z: vec2<f32>) -> f32 {
+    let log_zn = log(dot(z, z)) / 2.0;
+    let nu = log(log_zn / log(2.0)) / log(2.0);
+    return f32(i) + 1.0 - nu;
+}
+
 fn mandelbrot_smooth(c: vec2<f32>, max_iter: i32) -> f32 {
     var z = vec2<f32>(0.0, 0.0);
     for (var i = 0; i < max_iter; i = i + 1) {
         if (dot(z, z) > 16.0) {
-            let log_zn = log(dot(z,z)) / 2.0;
-            let nu = log(log_zn / log(2.0)) / log(2.0);
-            return f32(i) + 1.0 - nu;
+            return smooth_count(i, z);
         }
         z = vec2<f32>(
             z.x * z.x - z.y * z.y + c.x,
@@ -23,35 +36,89 @@ fn mandelbrot_smooth(c: vec2<f32>, max_iter: i32) -> f32 {
     return f32(max_iter);
 }
 
-fn hsv2rgb(hsv: vec3<f32>) -> vec3<f32> {
-    let c = hsv.z * hsv.y;
-    let h = hsv.x * 6.0;
-    let x = c * (1.0 - abs(h % 2.0 - 1.0));
-    var rgb: vec3<f32>;
+// Float-float arithmetic: a value is stored as vec2(hi, lo) with |lo| at most
+// half an ulp of hi, giving about 48 bits of mantissa from two f32s. The same
+// error-free transformations as `DoubleDouble` on the CPU.
+
+fn ff_two_sum(a: f32, b: f32) -> vec2<f32> {
+    let sum = a + b;
+    let b_virtual = sum - a;
+    return vec2<f32>(sum, (a - (sum - b_virtual)) + (b - b_virtual));
+}
 
-    if (h < 1.0) { rgb = vec3<f32>(c, x, 0.0); }
-    else if (h < 2.0) { rgb = vec3<f32>(x, c, 0.0); }
-    else if (h < 3.0) { rgb = vec3<f32>(0.0, c, x); }
-    else if (h < 4.0) { rgb = vec3<f32>(0.0, x, c); }
-    else if (h < 5.0) { rgb = vec3<f32>(x, 0.0, c); }
-    else { rgb = vec3<f32>(c, 0.0, x); }
+fn ff_quick_two_sum(a: f32, b: f32) -> vec2<f32> {
+    let sum = a + b;
+    return vec2<f32>(sum, b - (sum - a));
+}
+
+// Splits an f32 into two 12-bit halves with exact products (4097 = 2^12 + 1).
+fn ff_split(a: f32) -> vec2<f32> {
+    let t = 4097.0 * a;
+    let hi = t - (t - a);
+    return vec2<f32>(hi, a - hi);
+}
+
+fn ff_two_prod(a: f32, b: f32) -> vec2<f32> {
+    let product = a * b;
+    let a_split = ff_split(a);
+    let b_split = ff_split(b);
+    let error = ((a_split.x * b_split.x - product) + a_split.x * b_split.y
+        + a_split.y * b_split.x) + a_split.y * b_split.y;
+    return vec2<f32>(product, error);
+}
+
+fn ff_add(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    let sum = ff_two_sum(a.x, b.x);
+    let low = ff_two_sum(a.y, b.y);
+    let partial = ff_quick_two_sum(sum.x, sum.y + low.x);
+    return ff_quick_two_sum(partial.x, partial.y + low.y);
+}
+
+fn ff_sub(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    return ff_add(a, -b);
+}
 
-    return rgb + vec3<f32>(hsv.z - c);
+fn ff_mul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+    let product = ff_two_prod(a.x, b.x);
+    return ff_quick_two_sum(product.x, product.y + (a.x * b.y + a.y * b.x));
 }
 
-fn colorize_rainbow_gradient(n: f32, max_iter: i32) -> vec4<f32> {
+// `mandelbrot_smooth` with `c` and the orbit in float-float. The bailout test
+// and smoothing only need the hi parts.
+fn mandelbrot_smooth_ff(c_re: vec2<f32>, c_im: vec2<f32>, max_iter: i32) -> f32 {
+    var z_re = vec2<f32>(0.0, 0.0);
+    var z_im = vec2<f32>(0.0, 0.0);
+    for (var i = 0; i < max_iter; i = i + 1) {
+        let z = vec2<f32>(z_re.x, z_im.x);
+        if (dot(z, z) > 16.0) {
+            return smooth_count(i, z);
+        }
+        let cross = ff_mul(z_re, z_im);
+        z_re = ff_add(ff_sub(ff_mul(z_re, z_re), ff_mul(z_im, z_im)), c_re);
+        z_im = ff_add(ff_add(cross, cross), c_im);
+    }
+    return f32(max_iter);
+}
+
+// Looks up the palette built by `Palette::lookup_table`, applying the same
+// offset, density and wrap as `Palette::color` on the CPU.
+fn colorize_palette(n: f32, max_iter: i32) -> vec4<f32> {
     if (n >= f32(max_iter)) {
         return vec4<f32>(0.0, 0.0, 0.0, 1.0);
     }
 
-    let hue = fract(n / 256.0);
-
-    let saturation = 0.9;
-
-    let value = 1.0;
+    var t = uniforms.palette_offset + uniforms.palette_density * n;
+    if (uniforms.palette_cyclic != 0u) {
+        t = fract(t);
+    } else {
+        t = clamp(t, 0.0, 1.0);
+    }
 
-    let rgb = hsv2rgb(vec3(hue, saturation, value));
-    return vec4(rgb, 1.0);
+    let last = arrayLength(&palette) - 1u;
+    let position = t * f32(last);
+    let index = min(u32(position), last - 1u);
+    let color = mix(palette[index], palette[index + 1u], position - f32(index));
+    return vec4<f32>(color.rgb, 1.0);
 }
 
 
@@ -64,11 +131,24 @@ fn main_compute(@builtin(global_invocation_id) global_id: vec3<u32>) {
         return;
     }
 
-    let norm_coord = (frag_coord / dims) - 0.5;
-    let c = uniforms.center + vec2<f32>(norm_coord.x, -norm_coord.y) * uniforms.range;
-
-    let n_smooth = mandelbrot_smooth(c, uniforms.max_iter);
-    let color = colorize_rainbow_gradient(n_smooth, uniforms.max_iter);
+    let norm_coord = ((frag_coord + 0.5) / dims) - 0.5;
+    let offset = vec2<f32>(norm_coord.x, -norm_coord.y) * uniforms.range;
+    let sin_r = sin(uniforms.rotation);
+    let cos_r = cos(uniforms.rotation);
+    let rotated = vec2<f32>(
+        offset.x * cos_r - offset.y * sin_r,
+        offset.x * sin_r + offset.y * cos_r
+    );
+
+    var n_smooth: f32;
+    if (uniforms.emulated != 0u) {
+        let c_re = ff_add(vec2<f32>(uniforms.center_hi.x, uniforms.center_lo.x), vec2<f32>(rotated.x, 0.0));
+        let c_im = ff_add(vec2<f32>(uniforms.center_hi.y, uniforms.center_lo.y), vec2<f32>(rotated.y, 0.0));
+        n_smooth = mandelbrot_smooth_ff(c_re, c_im, uniforms.max_iter);
+    } else {
+        n_smooth = mandelbrot_smooth(uniforms.center_hi + rotated, uniforms.max_iter);
+    }
+    let color = colorize_palette(n_smooth, uniforms.max_iter);
 
     textureStore(storage_texture, global_id.xy, color);
 }
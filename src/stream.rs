@@ -0,0 +1,408 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use image::RgbImage;
+
+use crate::fractal::{RenderOptions, RenderStats, ViewRender};
+use crate::progress::Progress;
+use crate::tiles::Tile;
+use crate::viewport::Frame;
+
+/// Rows rendered at a time, and the side of TIFF tiles. Memory use is about
+/// one strip of the image.
+pub const STRIP_ROWS: u32 = 256;
+
+const TIFF_SHORT: u16 = 3;
+const TIFF_LONG: u16 = 4;
+const TIFF_LONG8: u16 = 16;
+/// Marks the dimensions a streamed TIFF records after its header.
+const STREAM_MARK: &[u8] = b"FRACTIFF";
+
+/// Renders a `width x height` view strip by strip through `render`, which
+/// receives `options` cropped to each strip, and writes every strip to `path`
+/// as soon as it is done, so that memory use does not grow with the image.
+/// Paths ending in `.tif` or `.tiff` get a tiled TIFF, which becomes a
+/// BigTIFF past 4 GiB; anything else gets a PNG.
+///
+/// With `resume`, the rows that an interrupted render already wrote to `path`
+/// are kept and rendering continues below them.
+pub fn render_to_file(
+    path: &Path,
+    width: u32,
+    height: u32,
+    options: &RenderOptions,
+    resume: bool,
+    render: &ViewRender,
+) -> io::Result<RenderStats> {
+    let tiff = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase())
+        .is_some_and(|extension| extension == "tif" || extension == "tiff");
+    let (mut writer, first_row): (Box<dyn StripWriter>, u32) = match tiff {
+        true => {
+            let (writer, first_row) = TiffWriter::open(path, width, height, resume)?;
+            (Box::new(writer), first_row)
+        }
+        false => {
+            let (writer, first_row) = PngWriter::open(path, width, height, resume)?;
+            (Box::new(writer), first_row)
+        }
+    };
+    if first_row > 0 {
+        println!("Resuming at row {} of {}.", first_row, height);
+    }
+
+    let mut strip_options = options.clone();
+    strip_options.progress = None;
+    let mut stats = RenderStats::default();
+    let total = width as u64 * height as u64;
+    for y in (first_row..height).step_by(STRIP_ROWS as usize) {
+        strip_options.crop = Some(Tile {
+            x: 0,
+            y,
+            width,
+            height: STRIP_ROWS.min(height - y),
+        });
+        let (strip, strip_stats) = render(Frame::new(width, height), &strip_options);
+        stats = stats + strip_stats;
+        if stats.cancelled {
+            return Ok(stats);
+        }
+        writer.write_strip(&strip)?;
+
+        if let Some(progress) = &options.progress {
+            let done = width as u64 * (y + strip.height()) as u64;
+            let _ = progress.send(Progress::Work { done, total });
+        }
+    }
+    writer.finish()?;
+    Ok(stats)
+}
+
+trait StripWriter {
+    /// Appends the next rows of the image. Strips are `STRIP_ROWS` high,
+    /// except possibly the last.
+    fn write_strip(&mut self, strip: &RgbImage) -> io::Result<()>;
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Streams rows into a PNG. Resuming decodes the complete rows of the
+/// partial file into a fresh one next to it, which replaces the partial file
+/// once the render is done.
+struct PngWriter {
+    stream: png::StreamWriter<'static, BufWriter<File>>,
+    /// The file being written and the path it is renamed to at the end.
+    rename: Option<(PathBuf, PathBuf)>,
+}
+
+impl PngWriter {
+    fn open(path: &Path, width: u32, height: u32, resume: bool) -> io::Result<(PngWriter, u32)> {
+        // The partial file is checked before anything is created next to it.
+        let partial = match resume && path.exists() {
+            true => {
+                let reader = png::Decoder::new(File::open(path)?)
+                    .read_info()
+                    .map_err(|error| {
+                        invalid_data(format!("{} cannot be resumed: {}", path.display(), error))
+                    })?;
+                let info = reader.info();
+                if (info.width, info.height) != (width, height)
+                    || info.color_type != png::ColorType::Rgb
+                    || info.bit_depth != png::BitDepth::Eight
+                {
+                    return Err(invalid_data(format!(
+                        "{} is not a partial {}x{} render",
+                        path.display(),
+                        width,
+                        height
+                    )));
+                }
+                Some(reader)
+            }
+            false => None,
+        };
+        let target = match partial {
+            Some(_) => {
+                let mut name = path.as_os_str().to_owned();
+                name.push(".resuming");
+                PathBuf::from(name)
+            }
+            None => path.to_path_buf(),
+        };
+
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(&target)?), width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut stream = encoder.write_header()?.into_stream_writer()?;
+
+        let mut rows = 0;
+        if let Some(mut reader) = partial {
+            // The rows up to where the file was cut off.
+            while let Ok(Some(row)) = reader.next_row() {
+                stream.write_all(row.data())?;
+                rows += 1;
+            }
+        }
+
+        let rename = (target != path).then(|| (target, path.to_path_buf()));
+        Ok((PngWriter { stream, rename }, rows))
+    }
+}
+
+impl StripWriter for PngWriter {
+    fn write_strip(&mut self, strip: &RgbImage) -> io::Result<()> {
+        self.stream.write_all(strip.as_raw())?;
+        // Keep what is done decodable if the render is interrupted.
+        self.stream.flush()
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.stream.finish()?;
+        if let Some((from, to)) = self.rename {
+            fs::rename(from, to)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes uncompressed `STRIP_ROWS`-square tiles, one row of tiles per
+/// strip. Tiles have fixed sizes and offsets, so the directory that lists
+/// them is only written at the end, and an interrupted file can be resumed
+/// after its last complete row of tiles.
+struct TiffWriter {
+    file: BufWriter<File>,
+    big: bool,
+    width: u32,
+    height: u32,
+}
+
+impl TiffWriter {
+    fn open(path: &Path, width: u32, height: u32, resume: bool) -> io::Result<(TiffWriter, u32)> {
+        let across = width.div_ceil(STRIP_ROWS) as u64;
+        let down = height.div_ceil(STRIP_ROWS) as u64;
+        // Tiles plus a generous allowance for the directory.
+        let size = across * down * (tile_bytes() + 16) + 4096;
+        let big = size > u32::MAX as u64;
+        let header = tiff_header(big, width, height);
+
+        let mut rows = 0;
+        let file = if resume && path.exists() {
+            let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+            let mut start = vec![0; header.len()];
+            // The format, then the dimensions recorded after the header.
+            let dimensions = header.len() - STREAM_MARK.len() - 8;
+            if file.read_exact(&mut start).is_err()
+                || start[..4] != header[..4]
+                || start[dimensions..] != header[dimensions..]
+            {
+                return Err(invalid_data(format!(
+                    "{} is not a partial {}x{} render",
+                    path.display(),
+                    width,
+                    height
+                )));
+            }
+            let complete = (file.metadata()?.len() - header.len() as u64) / tile_bytes();
+            let tile_rows = (complete / across).min(down);
+            file.set_len(header.len() as u64 + tile_rows * across * tile_bytes())?;
+            // Clear the directory offset of a finished file so that the
+            // directory is written afresh.
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&header)?;
+            file.seek(SeekFrom::End(0))?;
+            rows = (tile_rows as u32 * STRIP_ROWS).min(height);
+            file
+        } else {
+            let mut file = File::create(path)?;
+            file.write_all(&header)?;
+            file
+        };
+        let writer = TiffWriter {
+            file: BufWriter::new(file),
+            big,
+            width,
+            height,
+        };
+        Ok((writer, rows))
+    }
+
+    fn write_offset(&mut self, value: u64) -> io::Result<()> {
+        match self.big {
+            true => self.file.write_all(&value.to_le_bytes()),
+            false => self.file.write_all(&(value as u32).to_le_bytes()),
+        }
+    }
+}
+
+/// The file header, with the directory offset still zero, followed by
+/// `STREAM_MARK` and the image dimensions, which no directory entry points
+/// at, so that `--resume` can check that a partial file is the same render.
+fn tiff_header(big: bool, width: u32, height: u32) -> Vec<u8> {
+    let header = match big {
+        true => [
+            &b"II"[..],
+            &43u16.to_le_bytes(),
+            &8u16.to_le_bytes(),
+            &[0; 10],
+        ]
+        .concat(),
+        false => [&b"II"[..], &42u16.to_le_bytes(), &[0; 4]].concat(),
+    };
+    [
+        &header[..],
+        STREAM_MARK,
+        &width.to_le_bytes(),
+        &height.to_le_bytes(),
+    ]
+    .concat()
+}
+
+fn tile_bytes() -> u64 {
+    STRIP_ROWS as u64 * STRIP_ROWS as u64 * 3
+}
+
+/// Little-endian bytes of `values` as TIFF field type `kind`.
+fn field_bytes(kind: u16, values: &[u64]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|&value| match kind {
+            TIFF_SHORT => (value as u16).to_le_bytes().to_vec(),
+            TIFF_LONG => (value as u32).to_le_bytes().to_vec(),
+            _ => value.to_le_bytes().to_vec(),
+        })
+        .collect()
+}
+
+impl StripWriter for TiffWriter {
+    fn write_strip(&mut self, strip: &RgbImage) -> io::Result<()> {
+        let side = STRIP_ROWS as usize;
+        let mut tile = vec![0; tile_bytes() as usize];
+        for x in (0..strip.width()).step_by(side) {
+            let columns = STRIP_ROWS.min(strip.width() - x) as usize;
+            tile.fill(0);
+            for row in 0..strip.height() as usize {
+                let start = (row * strip.width() as usize + x as usize) * 3;
+                tile[row * side * 3..(row * side + columns) * 3]
+                    .copy_from_slice(&strip.as_raw()[start..start + columns * 3]);
+            }
+            self.file.write_all(&tile)?;
+        }
+        self.file.flush()
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        let tiles =
+            self.width.div_ceil(STRIP_ROWS) as u64 * self.height.div_ceil(STRIP_ROWS) as u64;
+        let header = tiff_header(self.big, self.width, self.height).len() as u64;
+        let offsets: Vec<u64> = (0..tiles)
+            .map(|tile| header + tile * tile_bytes())
+            .collect();
+        let counts = vec![tile_bytes(); tiles as usize];
+        let wide = if self.big { TIFF_LONG8 } else { TIFF_LONG };
+        let fields = [
+            (256, TIFF_LONG, vec![self.width as u64]),
+            (257, TIFF_LONG, vec![self.height as u64]),
+            (258, TIFF_SHORT, vec![8, 8, 8]),
+            // No compression, RGB, three samples per pixel, interleaved.
+            (259, TIFF_SHORT, vec![1]),
+            (262, TIFF_SHORT, vec![2]),
+            (277, TIFF_SHORT, vec![3]),
+            (284, TIFF_SHORT, vec![1]),
+            (322, TIFF_LONG, vec![STRIP_ROWS as u64]),
+            (323, TIFF_LONG, vec![STRIP_ROWS as u64]),
+            (324, wide, offsets),
+            (325, wide, counts),
+        ];
+
+        // Values too long to fit in their directory entry go first.
+        let inline = if self.big { 8 } else { 4 };
+        let mut position = self.file.seek(SeekFrom::End(0))?;
+        let mut entries = Vec::new();
+        for (tag, kind, values) in fields {
+            let mut bytes = field_bytes(kind, &values);
+            if bytes.len() > inline {
+                self.file.write_all(&bytes)?;
+                bytes = field_bytes(wide, &[position]);
+                position += (values.len() * field_bytes(kind, &[0]).len()) as u64;
+            }
+            bytes.resize(inline, 0);
+            entries.push((tag as u16, kind, values.len() as u64, bytes));
+        }
+
+        let directory = position;
+        match self.big {
+            true => self.file.write_all(&(entries.len() as u64).to_le_bytes())?,
+            false => self.file.write_all(&(entries.len() as u16).to_le_bytes())?,
+        }
+        for (tag, kind, count, value) in entries {
+            self.file.write_all(&tag.to_le_bytes())?;
+            self.file.write_all(&kind.to_le_bytes())?;
+            self.write_offset(count)?;
+            self.file.write_all(&value)?;
+        }
+        self.write_offset(0)?;
+
+        let header_offset = if self.big { 8 } else { 4 };
+        self.file.seek(SeekFrom::Start(header_offset))?;
+        self.write_offset(directory)?;
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::complex::Complex;
+    use crate::formula::Quadratic;
+    use crate::fractal;
+    use crate::viewport::Viewport;
+
+    fn render_view() -> (Viewport, RenderOptions) {
+        let viewport = Viewport::new(Complex::new(-0.75, 0.1), 0.05, 300, 600);
+        (viewport, RenderOptions::new(500))
+    }
+
+    #[test]
+    fn streamed_files_match_in_memory_render() {
+        let (viewport, options) = render_view();
+        let (expected, _) = fractal::mandelbrot(&Quadratic, &viewport, &options);
+        let render = |frame, options: &RenderOptions| {
+            fractal::mandelbrot(&Quadratic, &viewport.framed(frame), options)
+        };
+
+        let directory = std::env::temp_dir().join(format!("stream-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        for name in ["view.png", "view.tif"] {
+            let path = directory.join(name);
+            render_to_file(&path, 300, 600, &options, false, &render).unwrap();
+            assert_eq!(image::open(&path).unwrap().to_rgb8(), expected, "{}", name);
+
+            // Cut the file short and resume it.
+            let length = fs::metadata(&path).unwrap().len();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_len(length * 2 / 3)
+                .unwrap();
+            render_to_file(&path, 300, 600, &options, true, &render).unwrap();
+            assert_eq!(image::open(&path).unwrap().to_rgb8(), expected, "{}", name);
+
+            // A partial file of another size is refused, not appended to.
+            let length = fs::metadata(&path).unwrap().len();
+            let error = render_to_file(&path, 600, 300, &options, true, &render).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", name);
+            assert_eq!(fs::metadata(&path).unwrap().len(), length, "{}", name);
+            let mut resuming = path.clone().into_os_string();
+            resuming.push(".resuming");
+            assert!(!Path::new(&resuming).exists(), "{}", name);
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,194 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use rayon::prelude::*;
+
+/// A rectangle of pixels rendered as one unit of work.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    /// Row-major indices of the tile's pixels in an image `image_width` wide.
+    pub fn pixels(&self, image_width: u32) -> impl Iterator<Item = usize> + '_ {
+        (self.y..self.y + self.height).flat_map(move |y| {
+            (self.x..self.x + self.width)
+                .map(move |x| y as usize * image_width as usize + x as usize)
+        })
+    }
+}
+
+/// The order in which tiles are started.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TileOrder {
+    /// Left to right, top to bottom.
+    Rows,
+    /// Nearest to the image center first.
+    CenterOut,
+    /// A square spiral around the center tile.
+    Spiral,
+}
+
+impl TileOrder {
+    pub const NAMES: &'static [&'static str] = &["rows", "center", "spiral"];
+
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        match name {
+            "rows" => Some(TileOrder::Rows),
+            "center" => Some(TileOrder::CenterOut),
+            "spiral" => Some(TileOrder::Spiral),
+            _ => None,
+        }
+    }
+}
+
+/// Splits a `width x height` image into tiles of at most `size` pixels a
+/// side, listed in `order`.
+pub fn layout(width: u32, height: u32, size: u32, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let columns = width.div_ceil(size);
+    let rows = height.div_ceil(size);
+    let tile = |column: u32, row: u32| Tile {
+        x: column * size,
+        y: row * size,
+        width: size.min(width - column * size),
+        height: size.min(height - row * size),
+    };
+
+    match order {
+        TileOrder::Rows => (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| tile(column, row)))
+            .collect(),
+        TileOrder::CenterOut => {
+            let mut tiles: Vec<Tile> = layout(width, height, size, TileOrder::Rows);
+            let distance = |tile: &Tile| {
+                let dx = (tile.x * 2 + tile.width) as f64 - width as f64;
+                let dy = (tile.y * 2 + tile.height) as f64 - height as f64;
+                dx * dx + dy * dy
+            };
+            tiles.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+            tiles
+        }
+        TileOrder::Spiral => {
+            // Walk right, down, left, up with run lengths 1, 1, 2, 2, 3, 3,
+            // ..., keeping the positions that fall inside the grid.
+            let total = (columns * rows) as usize;
+            let mut tiles = Vec::with_capacity(total);
+            let (mut column, mut row) = ((columns as i64 - 1) / 2, (rows as i64 - 1) / 2);
+            let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+            let mut run = 1;
+            let mut turn = 0;
+            tiles.push(tile(column as u32, row as u32));
+            while tiles.len() < total {
+                let (dx, dy) = directions[turn % 4];
+                for _ in 0..run {
+                    column += dx;
+                    row += dy;
+                    if (0..columns as i64).contains(&column) && (0..rows as i64).contains(&row) {
+                        tiles.push(tile(column as u32, row as u32));
+                    }
+                }
+                turn += 1;
+                if turn % 2 == 0 {
+                    run += 1;
+                }
+            }
+            tiles
+        }
+    }
+}
+
+/// Shared flag that stops a render between tiles. Clones refer to the same
+/// flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Runs `work` on every tile across the rayon pool, starting tiles in the
+/// order given, and returns the results in that order. Returns `None`,
+/// without starting further tiles, once `cancel` is set.
+pub fn run<R, W>(tiles: &[Tile], cancel: &CancelToken, work: W) -> Option<Vec<R>>
+where
+    R: Send,
+    W: Fn(&Tile) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = (0..rayon::current_num_threads())
+        .into_par_iter()
+        .flat_map_iter(|_| {
+            let mut done = Vec::new();
+            while !cancel.is_cancelled() {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(tile) = tiles.get(index) else {
+                    break;
+                };
+                done.push((index, work(tile)));
+            }
+            done
+        })
+        .collect();
+    if cancel.is_cancelled() {
+        return None;
+    }
+    results.sort_by_key(|&(index, _)| index);
+    Some(results.into_iter().map(|(_, result)| result).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_cover_every_tile_once() {
+        let rows = layout(250, 130, 32, TileOrder::Rows);
+        assert_eq!(rows.len(), 8 * 5);
+        for order in [TileOrder::CenterOut, TileOrder::Spiral] {
+            let mut tiles = layout(250, 130, 32, order);
+            assert_eq!(tiles.len(), rows.len());
+            assert_eq!(
+                tiles[0],
+                Tile {
+                    x: 96,
+                    y: 64,
+                    width: 32,
+                    height: 32
+                }
+            );
+            tiles.sort_by_key(|tile| (tile.y, tile.x));
+            assert_eq!(tiles, rows);
+        }
+        let pixels: usize = rows.iter().map(|tile| tile.pixels(250).count()).sum();
+        assert_eq!(pixels, 250 * 130);
+    }
+
+    #[test]
+    fn cancelled_run_stops_between_tiles() {
+        let tiles = layout(64, 64, 1, TileOrder::Rows);
+        let cancel = CancelToken::new();
+        let started = AtomicUsize::new(0);
+        let result = run(&tiles, &cancel, |_| {
+            if started.fetch_add(1, Ordering::Relaxed) == 10 {
+                cancel.cancel();
+            }
+        });
+        assert!(result.is_none());
+        assert!(started.load(Ordering::Relaxed) < tiles.len());
+    }
+}
@@ -0,0 +1,220 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use image::ImageResult;
+
+use crate::complex::Complex;
+
+/// Which number about an orbit's approach to its trap is colored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrapInput {
+    /// The closest distance any orbit point came to the trap.
+    Min,
+    /// The mean distance over the orbit.
+    Average,
+    /// The iteration at which the orbit came closest.
+    Iteration,
+}
+
+/// The shape orbits are measured against.
+#[derive(Debug, Clone)]
+pub enum Shape {
+    Point,
+    /// An infinite line through the center along `direction`.
+    Line,
+    /// Two perpendicular lines crossing at the center, one along `direction`.
+    Cross,
+    /// A circle of radius `size` around the center.
+    Circle,
+    /// A bitmap `size` wide, centered on the center with its horizontal axis
+    /// along `direction`.
+    Image(Arc<TrapImage>),
+}
+
+impl Shape {
+    pub const NAMES: &'static [&'static str] = &["point", "line", "cross", "circle"];
+
+    pub fn from_name(name: &str) -> Option<Shape> {
+        match name {
+            "point" => Some(Shape::Point),
+            "line" => Some(Shape::Line),
+            "cross" => Some(Shape::Cross),
+            "circle" => Some(Shape::Circle),
+            _ => None,
+        }
+    }
+}
+
+/// An orbit trap: a shape placed in the plane that every orbit point is
+/// measured against.
+#[derive(Debug, Clone)]
+pub struct Trap {
+    pub shape: Shape,
+    pub center: Complex,
+    /// Unit vector the shape is turned to; `1` leaves it unturned.
+    pub direction: Complex,
+    pub size: f64,
+}
+
+impl Default for Trap {
+    fn default() -> Self {
+        Trap {
+            shape: Shape::Point,
+            center: Complex::ZERO,
+            direction: Complex::new(1.0, 0.0),
+            size: 1.0,
+        }
+    }
+}
+
+impl Trap {
+    /// Distance from `z` to the trap. For image traps this is one minus the
+    /// bitmap's coverage at `z`, so bright opaque pixels are near and
+    /// anything off the bitmap is at distance 1.
+    pub fn distance(&self, z: Complex) -> f64 {
+        // `z` in the trap's own frame, with the shape's axis along re.
+        let local = (z - self.center) * self.direction.conj();
+        match &self.shape {
+            Shape::Point => local.norm(),
+            Shape::Line => local.im.abs(),
+            Shape::Cross => local.re.abs().min(local.im.abs()),
+            Shape::Circle => (local.norm() - self.size).abs(),
+            Shape::Image(image) => 1.0 - image.coverage(local.scale(1.0 / self.size)),
+        }
+    }
+}
+
+/// A grayscale bitmap for image traps, stored as coverage: luminance times
+/// alpha, from 0 to 1.
+#[derive(Debug, Clone)]
+pub struct TrapImage {
+    pub width: u32,
+    pub height: u32,
+    pub coverage: Vec<f32>,
+}
+
+impl TrapImage {
+    pub fn open(path: &Path) -> ImageResult<TrapImage> {
+        let image = image::open(path)?.to_luma_alpha8();
+        let coverage = image
+            .pixels()
+            .map(|pixel| pixel[0] as f32 * pixel[1] as f32 / (255.0 * 255.0))
+            .collect();
+        Ok(TrapImage {
+            width: image.width(),
+            height: image.height(),
+            coverage,
+        })
+    }
+
+    /// Nearest-pixel coverage at `at`, in units of the image width with the
+    /// image centered on zero and its top row towards positive im.
+    fn coverage(&self, at: Complex) -> f64 {
+        let x = (at.re + 0.5) * self.width as f64;
+        let y = self.height as f64 / 2.0 - at.im * self.width as f64;
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return 0.0;
+        }
+        self.coverage[y as usize * self.width as usize + x as usize] as f64
+    }
+}
+
+/// Sees every point of an orbit as `Kernel` iterates it.
+pub trait OrbitVisitor {
+    /// Called with `z` after `iteration` steps, for every point inside the
+    /// bailout radius after the starting one.
+    fn visit(&mut self, iteration: u64, z: Complex);
+}
+
+impl OrbitVisitor for () {
+    fn visit(&mut self, _: u64, _: Complex) {}
+}
+
+/// Collects an orbit's distances to `trap`.
+pub struct TrapVisitor<'a> {
+    trap: &'a Trap,
+    min: f64,
+    closest: u64,
+    sum: f64,
+    count: u64,
+}
+
+impl<'a> TrapVisitor<'a> {
+    pub fn new(trap: &'a Trap) -> Self {
+        TrapVisitor {
+            trap,
+            min: f64::INFINITY,
+            closest: 0,
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    /// The requested input over the points visited so far, NaN if none were.
+    pub fn value(&self, input: TrapInput) -> f64 {
+        if self.count == 0 {
+            return f64::NAN;
+        }
+        match input {
+            TrapInput::Min => self.min,
+            TrapInput::Average => self.sum / self.count as f64,
+            TrapInput::Iteration => self.closest as f64,
+        }
+    }
+}
+
+impl OrbitVisitor for TrapVisitor<'_> {
+    fn visit(&mut self, iteration: u64, z: Complex) {
+        let distance = self.trap.distance(z);
+        if distance < self.min {
+            self.min = distance;
+            self.closest = iteration;
+        }
+        self.sum += distance;
+        self.count += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances_to_shapes() {
+        let turned = |shape| Trap {
+            shape,
+            center: Complex::new(1.0, 1.0),
+            direction: Complex::new(0.0, 1.0),
+            size: 2.0,
+        };
+        let z = Complex::new(4.0, 5.0);
+        assert_eq!(turned(Shape::Point).distance(z), 5.0);
+        assert_eq!(turned(Shape::Line).distance(z), 3.0);
+        assert_eq!(turned(Shape::Cross).distance(z), 3.0);
+        assert_eq!(turned(Shape::Circle).distance(z), 3.0);
+
+        // A 2x1 bitmap, 2 units wide: the left pixel white, the right clear.
+        let image = TrapImage {
+            width: 2,
+            height: 1,
+            coverage: vec![1.0, 0.0],
+        };
+        let trap = Trap {
+            shape: Shape::Image(Arc::new(image)),
+            size: 2.0,
+            ..Trap::default()
+        };
+        assert_eq!(trap.distance(Complex::new(-0.5, 0.2)), 0.0);
+        assert_eq!(trap.distance(Complex::new(0.5, 0.2)), 1.0);
+        assert_eq!(trap.distance(Complex::new(-0.5, 0.7)), 1.0);
+
+        let mut visitor = TrapVisitor::new(&trap);
+        assert!(visitor.value(TrapInput::Min).is_nan());
+        for (iteration, re) in [(1, 3.0), (2, -0.5), (3, 0.5)] {
+            visitor.visit(iteration, Complex::new(re, 0.0));
+        }
+        assert_eq!(visitor.value(TrapInput::Min), 0.0);
+        assert_eq!(visitor.value(TrapInput::Iteration), 2.0);
+        assert_eq!(visitor.value(TrapInput::Average), 2.0 / 3.0);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::complex::Complex;
+use crate::real::Real;
+
+/// A rectangular view onto the complex plane, shared by the file renderer and
+/// the interactive viewer so that both map pixels to the same points.
+///
+/// `span` is the extent of the shorter image side in the complex plane, which
+/// keeps the visible region stable when the aspect ratio changes. `rotation`
+/// is counter-clockwise, in radians. Pixel coordinates grow right and down,
+/// and the center of pixel `(x, y)` sits at `(x + 0.5, y + 0.5)`.
+///
+/// Only `center` is stored in the render precision `T`; everything measured
+/// within the view is small enough for `f64`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport<T = f64> {
+    pub center: Complex<T>,
+    pub span: f64,
+    pub rotation: f64,
+    pub width: u32,
+    pub height: u32,
+    /// Offset of the image center from `center`, in pixels right and down.
+    /// Lets a view drawn at another size keep an edge where it was, which
+    /// moving `center` by a fraction of a pixel could not do at depth.
+    pub shift: [f64; 2],
+}
+
+/// Size and scale at which to draw a view, for renders that need it at
+/// other sizes than its own, such as tile pyramids.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    /// Extent of the shorter side relative to the view's `span`.
+    pub span_scale: f64,
+    /// Offset of the frame's center from the view's, in frame pixels right
+    /// and down.
+    pub shift: [f64; 2],
+}
+
+impl Frame {
+    pub fn new(width: u32, height: u32) -> Self {
+        Frame {
+            width,
+            height,
+            span_scale: 1.0,
+            shift: [0.0, 0.0],
+        }
+    }
+}
+
+impl<T: Real> Viewport<T> {
+    pub fn new(center: Complex<T>, span: f64, width: u32, height: u32) -> Self {
+        Viewport {
+            center,
+            span,
+            rotation: 0.0,
+            width,
+            height,
+            shift: [0.0, 0.0],
+        }
+    }
+
+    /// Distance in the complex plane between neighbouring pixel centers.
+    pub fn pixel_size(&self) -> f64 {
+        self.span / self.width.min(self.height) as f64
+    }
+
+    /// Complex-plane extent of the full image, `[horizontal, vertical]`.
+    pub fn extent(&self) -> [f64; 2] {
+        let pixel_size = self.pixel_size();
+        [
+            self.width as f64 * pixel_size,
+            self.height as f64 * pixel_size,
+        ]
+    }
+
+    pub fn pixel_to_complex(&self, x: f64, y: f64) -> Complex<T> {
+        self.center + Complex::from_f64(self.pixel_offset(x, y))
+    }
+
+    /// Position of pixel `(x, y)` relative to `center`. Deep zooms use this
+    /// directly, since adding it to an `f64` center would lose its digits.
+    pub fn pixel_offset(&self, x: f64, y: f64) -> Complex {
+        let pixel_size = self.pixel_size();
+        let dx = (x - self.width as f64 / 2.0 + self.shift[0]) * pixel_size;
+        let dy = (self.height as f64 / 2.0 - y - self.shift[1]) * pixel_size;
+        let (sin, cos) = self.rotation.sin_cos();
+
+        Complex::new(dx * cos - dy * sin, dx * sin + dy * cos)
+    }
+
+    pub fn complex_to_pixel(&self, c: Complex<T>) -> (f64, f64) {
+        let pixel_size = self.pixel_size();
+        let offset = (c - self.center).to_f64();
+        let (sin, cos) = self.rotation.sin_cos();
+        let dx = offset.re * cos + offset.im * sin;
+        let dy = -offset.re * sin + offset.im * cos;
+
+        (
+            self.width as f64 / 2.0 - self.shift[0] + dx / pixel_size,
+            self.height as f64 / 2.0 - self.shift[1] - dy / pixel_size,
+        )
+    }
+
+    /// Scales the span by `factor` while keeping the point under pixel
+    /// `(x, y)` fixed on screen.
+    pub fn zoom_at(&mut self, x: f64, y: f64, factor: f64) {
+        let anchor = self.pixel_to_complex(x, y);
+        self.span *= factor;
+        let (new_x, new_y) = self.complex_to_pixel(anchor);
+        self.pan(x - new_x, y - new_y);
+    }
+
+    /// This view drawn at the size of `frame`, with its shorter side spanning
+    /// `frame.span_scale` times `span` and its center moved by `frame.shift`.
+    pub fn framed(&self, frame: Frame) -> Self {
+        let mut framed = Viewport {
+            span: self.span * frame.span_scale,
+            width: frame.width,
+            height: frame.height,
+            ..*self
+        };
+        let scale = self.pixel_size() / framed.pixel_size();
+        framed.shift = [0, 1].map(|axis| self.shift[axis] * scale + frame.shift[axis]);
+        framed
+    }
+
+    /// Moves the view so that content shifts by `(dx, dy)` pixels on screen.
+    pub fn pan(&mut self, dx: f64, dy: f64) {
+        let origin = self.pixel_to_complex(0.0, 0.0);
+        let moved = self.pixel_to_complex(dx, dy);
+        self.center = self.center - (moved - origin);
+    }
+}
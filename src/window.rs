@@ -1,24 +1,206 @@
-use bytemuck;
+use std::sync::mpsc;
+use std::thread;
+
+use image::RgbImage;
 use winit::{
     dpi::PhysicalPosition,
-    event::{ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    event::{
+        ElementState, Event, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode,
+        WindowEvent,
+    },
+    event_loop::{ControlFlow, EventLoopBuilder, EventLoopProxy},
+    window::{Window, WindowBuilder},
 };
 
+use crate::coloring::ITERATIONS_PER_CYCLE;
+use crate::complex::Complex;
+use crate::double_double::DoubleDouble;
+use crate::formula::Quadratic;
+use crate::fractal::{self, RenderOptions};
+use crate::palette::{self, Palette, Wrap};
+use crate::progress::Progress;
+use crate::real::Precision;
+use crate::tiles::CancelToken;
+use crate::viewport::Viewport;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 struct Uniforms {
-    center: [f32; 2],
+    center_hi: [f32; 2],
+    center_lo: [f32; 2],
     range: [f32; 2],
     max_iter: i32,
-    _padding: [u32; 3],
+    rotation: f32,
+    palette_offset: f32,
+    palette_density: f32,
+    palette_cyclic: u32,
+    emulated: u32,
 }
 
-const INITIAL_ITERATIONS: i32 = 500;
+impl Uniforms {
+    fn new(viewport: &Viewport, max_iter: i32, palette: &Palette) -> Self {
+        let [range_x, range_y] = viewport.extent();
+        let (re_hi, re_lo) = split_f64(viewport.center.re);
+        let (im_hi, im_lo) = split_f64(viewport.center.im);
+        Uniforms {
+            center_hi: [re_hi, im_hi],
+            center_lo: [re_lo, im_lo],
+            range: [range_x as f32, range_y as f32],
+            max_iter,
+            rotation: viewport.rotation as f32,
+            palette_offset: palette.offset as f32,
+            palette_density: (palette.density / ITERATIONS_PER_CYCLE) as f32,
+            palette_cyclic: (palette.wrap == Wrap::Cyclic) as u32,
+            emulated: needs_emulation(viewport) as u32,
+        }
+    }
+}
+
+/// Splits `value` into the float-float pair `hi + lo` read by the shader.
+fn split_f64(value: f64) -> (f32, f32) {
+    let hi = value as f32;
+    (hi, (value - hi as f64) as f32)
+}
+
+/// Whether neighbouring pixels are closer than `f32` can resolve, so the
+/// shader has to iterate in float-float.
+fn needs_emulation(viewport: &Viewport) -> bool {
+    Precision::auto(viewport.pixel_size(), viewport.center.norm()) != Some(Precision::F32)
+}
+
+/// Whether neighbouring pixels are closer than even float-float can resolve.
+fn at_zoom_limit(viewport: &Viewport) -> bool {
+    viewport.pixel_size() < FLOAT_FLOAT_EPSILON * viewport.center.norm().max(1.0)
+}
+
+/// Progress of a CPU render of the view, tagged with the `generation` of the
+/// view it was started for.
+struct CpuProgress {
+    generation: u64,
+    progress: Progress,
+}
+
+/// Renders `viewport` on the CPU in the background, in double-double where
+/// `f64` cannot resolve it, sending the coarse passes and then the final image
+/// to the event loop as `Progress::Preview`s, unless `cancel` is set first.
+fn spawn_cpu_render(
+    viewport: Viewport,
+    max_iter: i32,
+    palette: &Palette,
+    generation: u64,
+    cancel: CancelToken,
+    proxy: EventLoopProxy<CpuProgress>,
+) {
+    let (sender, receiver) = mpsc::channel();
+    let mut options = RenderOptions::new(max_iter as u64);
+    options.palette = palette.clone();
+    options.progress = Some(sender);
+    options.cancel = cancel;
+
+    let forward = proxy.clone();
+    thread::spawn(move || {
+        for progress in receiver {
+            let event = CpuProgress {
+                generation,
+                progress,
+            };
+            if forward.send_event(event).is_err() {
+                break;
+            }
+        }
+    });
+    thread::spawn(move || {
+        let (image, stats) = match Precision::auto(viewport.pixel_size(), viewport.center.norm()) {
+            Some(Precision::F32 | Precision::F64) => {
+                fractal::mandelbrot(&Quadratic, &viewport, &options)
+            }
+            _ => {
+                let precise = Viewport {
+                    center: Complex::<DoubleDouble>::from_f64(viewport.center),
+                    span: viewport.span,
+                    rotation: viewport.rotation,
+                    width: viewport.width,
+                    height: viewport.height,
+                    shift: viewport.shift,
+                };
+                fractal::mandelbrot(&Quadratic, &precise, &options)
+            }
+        };
+        if stats.cancelled {
+            return;
+        }
+        let progress = Progress::Preview { step: 1, image };
+        let _ = proxy.send_event(CpuProgress {
+            generation,
+            progress,
+        });
+    });
+}
+
+/// Hands a changed view to the shader: uploads its `uniforms`, bumps
+/// `generation` and cancels the CPU render of the old view, whose progress
+/// is cleared from the title.
+fn show_view(
+    queue: &wgpu::Queue,
+    uniform_buffer: &wgpu::Buffer,
+    window: &Window,
+    uniforms: Uniforms,
+    generation: &mut u64,
+    cpu_cancel: &CancelToken,
+) {
+    queue.write_buffer(uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+    *generation += 1;
+    cpu_cancel.cancel();
+    window.set_title("Mandelbrot");
+    window.request_redraw();
+}
 
-pub async fn run_window() {
-    let event_loop = EventLoop::new();
+/// Copies a rendered sRGB image into the storage texture at `(x, y)`. The
+/// texture holds linear colors, like the compute shader writes.
+fn upload(queue: &wgpu::Queue, texture: &wgpu::Texture, image: &RgbImage, (x, y): (u32, u32)) {
+    let rgba: Vec<u8> = image
+        .pixels()
+        .flat_map(|pixel| {
+            let [r, g, b] = pixel
+                .0
+                .map(|c| (palette::srgb_to_linear(c as f64 / 255.0) * 255.0).round() as u8);
+            [r, g, b, 255]
+        })
+        .collect();
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x, y, z: 0 },
+            aspect: wgpu::TextureAspect::All,
+        },
+        &rgba,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(4 * image.width()),
+            rows_per_image: Some(image.height()),
+        },
+        wgpu::Extent3d {
+            width: image.width(),
+            height: image.height(),
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+const INITIAL_ITERATIONS: i32 = 500;
+const INITIAL_SPAN: f64 = 2.0;
+const ROTATION_STEP: f64 = 5.0;
+const PALETTE_LUT_SIZE: usize = 1024;
+/// Relative precision of the shader's float-float path, `2^-48`.
+const FLOAT_FLOAT_EPSILON: f64 = 3.552713678800501e-15;
+
+#[allow(clippy::collapsible_match)]
+/// Opens the interactive viewer with `palette`, which `--palette
+/// <palette_name>` selects on the command line.
+pub async fn run_window(mut palette_name: String, mut palette: Palette) {
+    let event_loop = EventLoopBuilder::<CpuProgress>::with_user_event().build();
+    let proxy = event_loop.create_proxy();
     let window = WindowBuilder::new()
         .with_title("Mandelbrot")
         .build(&event_loop)
@@ -55,12 +237,14 @@ pub async fn run_window() {
     };
     surface.configure(&device, &config);
 
-    let mut uniforms = Uniforms {
-        center: [-0.75, 0.0],
-        range: [3.5, 2.0],
-        max_iter: INITIAL_ITERATIONS,
-        _padding: [0; 3],
-    };
+    let mut viewport = Viewport::new(
+        Complex::new(-0.75, 0.0),
+        INITIAL_SPAN,
+        config.width,
+        config.height,
+    );
+    let mut max_iter = INITIAL_ITERATIONS;
+    let uniforms = Uniforms::new(&viewport, max_iter, &palette);
     let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
         label: Some("Uniform Buffer"),
         size: std::mem::size_of::<Uniforms>() as wgpu::BufferAddress,
@@ -69,6 +253,24 @@ pub async fn run_window() {
     });
     queue.write_buffer(&uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
 
+    let palette_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Palette Buffer"),
+        size: ((PALETTE_LUT_SIZE + 1) * std::mem::size_of::<[f32; 4]>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    queue.write_buffer(
+        &palette_buffer,
+        0,
+        bytemuck::cast_slice(&palette.lookup_table(PALETTE_LUT_SIZE)),
+    );
+    let mut builtin_index = 0;
+    // Bumped on every change to the view, which also cancels any CPU render
+    // of the old view and hands the display back to the shader.
+    let mut generation: u64 = 0;
+    let mut cpu_generation: Option<u64> = None;
+    let mut cpu_cancel = CancelToken::new();
+
     let mut mouse_pos = PhysicalPosition::new(0.0, 0.0);
     let mut last_mouse_pos = PhysicalPosition::new(0.0, 0.0);
     let mut panning = false;
@@ -84,7 +286,9 @@ pub async fn run_window() {
         sample_count: 1,
         dimension: wgpu::TextureDimension::D2,
         format: wgpu::TextureFormat::Rgba8Unorm,
-        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+        usage: wgpu::TextureUsages::STORAGE_BINDING
+            | wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST,
         view_formats: &[],
     });
 
@@ -95,16 +299,28 @@ pub async fn run_window() {
 
     let compute_uniform_bgl = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
         label: Some("Compute Uniform BGL"),
-        entries: &[wgpu::BindGroupLayoutEntry {
-            binding: 0,
-            visibility: wgpu::ShaderStages::COMPUTE,
-            ty: wgpu::BindingType::Buffer {
-                ty: wgpu::BufferBindingType::Uniform,
-                has_dynamic_offset: false,
-                min_binding_size: None,
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
             },
-            count: None,
-        }],
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
     });
     let compute_storage_bgl = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
         label: Some("Compute Storage BGL"),
@@ -207,15 +423,21 @@ pub async fn run_window() {
                         dimension: wgpu::TextureDimension::D2,
                         format: wgpu::TextureFormat::Rgba8Unorm,
                         usage: wgpu::TextureUsages::STORAGE_BINDING
-                            | wgpu::TextureUsages::TEXTURE_BINDING,
+                            | wgpu::TextureUsages::TEXTURE_BINDING
+                            | wgpu::TextureUsages::COPY_DST,
                         view_formats: &[],
                     });
 
-                    let aspect_ratio = config.width as f32 / config.height as f32;
-                    uniforms.range[1] = uniforms.range[0] / aspect_ratio;
-                    queue.write_buffer(&uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
-
-                    window.request_redraw();
+                    viewport.width = config.width;
+                    viewport.height = config.height;
+                    show_view(
+                        &queue,
+                        &uniform_buffer,
+                        &window,
+                        Uniforms::new(&viewport, max_iter, &palette),
+                        &mut generation,
+                        &cpu_cancel,
+                    );
                 }
             }
             WindowEvent::CursorMoved { position, .. } => {
@@ -224,16 +446,18 @@ pub async fn run_window() {
                     let dx = mouse_pos.x - last_mouse_pos.x;
                     let dy = mouse_pos.y - last_mouse_pos.y;
 
-                    let delta_complex_x = (dx as f32 / config.width as f32) * uniforms.range[0];
-                    let delta_complex_y = (dy as f32 / config.height as f32) * uniforms.range[1];
-
-                    uniforms.center[0] -= delta_complex_x;
-                    uniforms.center[1] -= delta_complex_y;
+                    viewport.pan(dx, dy);
 
                     last_mouse_pos = mouse_pos;
 
-                    queue.write_buffer(&uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
-                    window.request_redraw();
+                    show_view(
+                        &queue,
+                        &uniform_buffer,
+                        &window,
+                        Uniforms::new(&viewport, max_iter, &palette),
+                        &mut generation,
+                        &cpu_cancel,
+                    );
                 }
             }
             WindowEvent::MouseWheel { delta, .. } => {
@@ -248,34 +472,120 @@ pub async fn run_window() {
 
                 let zoom_factor = if scroll > 0.0 { 1.0 / 1.1 } else { 1.1 };
 
-                let min_zoom_range = 1.0e-4;
-                if zoom_factor < 1.0 && uniforms.range[0] < min_zoom_range {
+                if zoom_factor < 1.0 && at_zoom_limit(&viewport) {
                     println!("Zoom limit reached.");
                     return;
                 }
-                if zoom_factor > 1.0 && uniforms.range[0] > 5.0 {
+                if zoom_factor > 1.0 && viewport.span > 5.0 {
                     return;
                 }
 
-                let norm_x = (mouse_pos.x as f32 / config.width as f32) - 0.5;
-                let norm_y = (mouse_pos.y as f32 / config.height as f32) - 0.5;
-
-                let mouse_complex_x = uniforms.center[0] + norm_x * uniforms.range[0];
-                let mouse_complex_y = uniforms.center[1] + norm_y * uniforms.range[1];
-
-                uniforms.range[0] *= zoom_factor;
-                uniforms.range[1] *= zoom_factor;
-
-                uniforms.center[0] = mouse_complex_x - norm_x * uniforms.range[0];
-                uniforms.center[1] = mouse_complex_y - norm_y * uniforms.range[1];
+                let was_emulated = needs_emulation(&viewport);
+                viewport.zoom_at(mouse_pos.x, mouse_pos.y, zoom_factor);
+                if needs_emulation(&viewport) != was_emulated {
+                    match was_emulated {
+                        false => println!("Switching to emulated double precision."),
+                        true => println!("Switching to single precision."),
+                    }
+                }
 
-                uniforms.max_iter = (INITIAL_ITERATIONS as f32
-                    * (3.5 / uniforms.range[0]).powf(0.3))
-                .clamp(128.0, 5000.0) as i32;
+                max_iter = (INITIAL_ITERATIONS as f64 * (INITIAL_SPAN / viewport.span).powf(0.3))
+                    .clamp(128.0, 5000.0) as i32;
 
-                queue.write_buffer(&uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
-                window.request_redraw();
+                show_view(
+                    &queue,
+                    &uniform_buffer,
+                    &window,
+                    Uniforms::new(&viewport, max_iter, &palette),
+                    &mut generation,
+                    &cpu_cancel,
+                );
             }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => match key {
+                VirtualKeyCode::Q | VirtualKeyCode::E => {
+                    let step = if key == VirtualKeyCode::Q {
+                        ROTATION_STEP
+                    } else {
+                        -ROTATION_STEP
+                    };
+                    viewport.rotation += step.to_radians();
+
+                    show_view(
+                        &queue,
+                        &uniform_buffer,
+                        &window,
+                        Uniforms::new(&viewport, max_iter, &palette),
+                        &mut generation,
+                        &cpu_cancel,
+                    );
+                }
+                VirtualKeyCode::C => {
+                    builtin_index = (builtin_index + 1) % Palette::BUILTIN_NAMES.len();
+                    let name = Palette::BUILTIN_NAMES[builtin_index];
+                    let mut next = Palette::builtin(name).unwrap();
+                    next.interpolation = palette.interpolation;
+                    next.offset = palette.offset;
+                    next.density = palette.density;
+                    next.wrap = palette.wrap;
+                    palette = next;
+                    palette_name = name.to_string();
+                    println!("Palette: {}", name);
+
+                    queue.write_buffer(
+                        &palette_buffer,
+                        0,
+                        bytemuck::cast_slice(&palette.lookup_table(PALETTE_LUT_SIZE)),
+                    );
+                    show_view(
+                        &queue,
+                        &uniform_buffer,
+                        &window,
+                        Uniforms::new(&viewport, max_iter, &palette),
+                        &mut generation,
+                        &cpu_cancel,
+                    );
+                }
+                VirtualKeyCode::R => {
+                    println!("Rendering the view on the CPU.");
+                    cpu_cancel.cancel();
+                    cpu_cancel = CancelToken::new();
+                    spawn_cpu_render(
+                        viewport,
+                        max_iter,
+                        &palette,
+                        generation,
+                        cpu_cancel.clone(),
+                        proxy.clone(),
+                    );
+                }
+                VirtualKeyCode::P => {
+                    println!(
+                        "cargo run --release <output.png> 0 {} {} {} 1 --span {} --width {} --height {} --rotation {} \
+                         --palette {} --interpolation {} --palette-mode {} --palette-offset {} --palette-density {}",
+                        max_iter,
+                        viewport.center.re,
+                        viewport.center.im,
+                        viewport.span,
+                        viewport.width,
+                        viewport.height,
+                        viewport.rotation.to_degrees(),
+                        palette_name,
+                        palette.interpolation.name(),
+                        palette.wrap.name(),
+                        palette.offset,
+                        palette.density,
+                    );
+                }
+                _ => {}
+            },
             WindowEvent::MouseInput { state, button, .. } => {
                 if button == MouseButton::Left {
                     match state {
@@ -291,6 +601,39 @@ pub async fn run_window() {
             }
             _ => {}
         },
+        Event::UserEvent(CpuProgress {
+            generation: rendered,
+            progress,
+        }) if rendered == generation => match progress {
+            Progress::Work { done, total } => {
+                let percent = 100.0 * done as f64 / total.max(1) as f64;
+                window.set_title(&format!("Mandelbrot - rendering {:.0}%", percent));
+            }
+            Progress::Preview { step, image } => {
+                if (image.width(), image.height()) == (config.width, config.height) {
+                    upload(&queue, &storage_texture, &image, (0, 0));
+                    cpu_generation = Some(generation);
+                    window.request_redraw();
+                }
+                if step == 1 {
+                    window.set_title("Mandelbrot");
+                }
+            }
+            Progress::Tile {
+                tile,
+                image: Some(image),
+            } => {
+                if tile.x + tile.width <= config.width && tile.y + tile.height <= config.height {
+                    upload(&queue, &storage_texture, &image, (tile.x, tile.y));
+                    cpu_generation = Some(generation);
+                    window.request_redraw();
+                }
+            }
+            Progress::Tile { image: None, .. } => {}
+        },
+        // Left over from a cancelled render of an older view, whose progress
+        // was cleared from the title when it was cancelled.
+        Event::UserEvent(_) => {}
         Event::RedrawRequested(_) => {
             let frame = surface
                 .get_current_texture()
@@ -304,10 +647,16 @@ pub async fn run_window() {
             let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
                 label: Some("Uniform BG"),
                 layout: &compute_uniform_bgl,
-                entries: &[wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: uniform_buffer.as_entire_binding(),
-                }],
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: palette_buffer.as_entire_binding(),
+                    },
+                ],
             });
             let storage_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
                 label: Some("Storage BG"),
@@ -335,7 +684,7 @@ pub async fn run_window() {
             let mut encoder =
                 device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
 
-            {
+            if cpu_generation != Some(generation) {
                 let mut compute_pass =
                     encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
 